@@ -0,0 +1,555 @@
+//! Mapping of gamepad axes and buttons to game actions.
+//!
+//! Profiles are stored in preferences by controller UUID so that every controller of the same
+//! model shares its bindings, the keyboard has a single profile of menu keys.
+
+use gilrs;
+use specs;
+
+/// Serializable mirror of `gilrs::ev::Axis`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Axis {
+    LeftStickX,
+    LeftStickY,
+    LeftZ,
+    RightStickX,
+    RightStickY,
+    RightZ,
+    DPadX,
+    DPadY,
+}
+
+impl Axis {
+    pub fn from_gilrs(axis: gilrs::ev::Axis) -> Option<Self> {
+        match axis {
+            gilrs::ev::Axis::LeftStickX => Some(Axis::LeftStickX),
+            gilrs::ev::Axis::LeftStickY => Some(Axis::LeftStickY),
+            gilrs::ev::Axis::LeftZ => Some(Axis::LeftZ),
+            gilrs::ev::Axis::RightStickX => Some(Axis::RightStickX),
+            gilrs::ev::Axis::RightStickY => Some(Axis::RightStickY),
+            gilrs::ev::Axis::RightZ => Some(Axis::RightZ),
+            gilrs::ev::Axis::DPadX => Some(Axis::DPadX),
+            gilrs::ev::Axis::DPadY => Some(Axis::DPadY),
+            _ => None,
+        }
+    }
+
+    pub fn to_gilrs(&self) -> gilrs::ev::Axis {
+        match self {
+            Axis::LeftStickX => gilrs::ev::Axis::LeftStickX,
+            Axis::LeftStickY => gilrs::ev::Axis::LeftStickY,
+            Axis::LeftZ => gilrs::ev::Axis::LeftZ,
+            Axis::RightStickX => gilrs::ev::Axis::RightStickX,
+            Axis::RightStickY => gilrs::ev::Axis::RightStickY,
+            Axis::RightZ => gilrs::ev::Axis::RightZ,
+            Axis::DPadX => gilrs::ev::Axis::DPadX,
+            Axis::DPadY => gilrs::ev::Axis::DPadY,
+        }
+    }
+
+    /// Horizontal and vertical axes of the stick the axis belongs to.
+    ///
+    /// Some controllers report their right stick as `LeftZ` and `RightZ`.
+    pub fn stick(&self) -> [Axis; 2] {
+        match self {
+            Axis::LeftStickX | Axis::LeftStickY => [Axis::LeftStickX, Axis::LeftStickY],
+            Axis::RightStickX | Axis::RightStickY => [Axis::RightStickX, Axis::RightStickY],
+            Axis::LeftZ | Axis::RightZ => [Axis::LeftZ, Axis::RightZ],
+            Axis::DPadX | Axis::DPadY => [Axis::DPadX, Axis::DPadY],
+        }
+    }
+}
+
+/// Serializable mirror of `gilrs::ev::Button`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    South,
+    East,
+    North,
+    West,
+    C,
+    Z,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    Mode,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl Button {
+    pub fn from_gilrs(button: gilrs::ev::Button) -> Option<Self> {
+        match button {
+            gilrs::ev::Button::South => Some(Button::South),
+            gilrs::ev::Button::East => Some(Button::East),
+            gilrs::ev::Button::North => Some(Button::North),
+            gilrs::ev::Button::West => Some(Button::West),
+            gilrs::ev::Button::C => Some(Button::C),
+            gilrs::ev::Button::Z => Some(Button::Z),
+            gilrs::ev::Button::LeftTrigger => Some(Button::LeftTrigger),
+            gilrs::ev::Button::LeftTrigger2 => Some(Button::LeftTrigger2),
+            gilrs::ev::Button::RightTrigger => Some(Button::RightTrigger),
+            gilrs::ev::Button::RightTrigger2 => Some(Button::RightTrigger2),
+            gilrs::ev::Button::Select => Some(Button::Select),
+            gilrs::ev::Button::Start => Some(Button::Start),
+            gilrs::ev::Button::Mode => Some(Button::Mode),
+            gilrs::ev::Button::LeftThumb => Some(Button::LeftThumb),
+            gilrs::ev::Button::RightThumb => Some(Button::RightThumb),
+            gilrs::ev::Button::DPadUp => Some(Button::DPadUp),
+            gilrs::ev::Button::DPadDown => Some(Button::DPadDown),
+            gilrs::ev::Button::DPadLeft => Some(Button::DPadLeft),
+            gilrs::ev::Button::DPadRight => Some(Button::DPadRight),
+            _ => None,
+        }
+    }
+
+    pub fn to_gilrs(&self) -> gilrs::ev::Button {
+        match self {
+            Button::South => gilrs::ev::Button::South,
+            Button::East => gilrs::ev::Button::East,
+            Button::North => gilrs::ev::Button::North,
+            Button::West => gilrs::ev::Button::West,
+            Button::C => gilrs::ev::Button::C,
+            Button::Z => gilrs::ev::Button::Z,
+            Button::LeftTrigger => gilrs::ev::Button::LeftTrigger,
+            Button::LeftTrigger2 => gilrs::ev::Button::LeftTrigger2,
+            Button::RightTrigger => gilrs::ev::Button::RightTrigger,
+            Button::RightTrigger2 => gilrs::ev::Button::RightTrigger2,
+            Button::Select => gilrs::ev::Button::Select,
+            Button::Start => gilrs::ev::Button::Start,
+            Button::Mode => gilrs::ev::Button::Mode,
+            Button::LeftThumb => gilrs::ev::Button::LeftThumb,
+            Button::RightThumb => gilrs::ev::Button::RightThumb,
+            Button::DPadUp => gilrs::ev::Button::DPadUp,
+            Button::DPadDown => gilrs::ev::Button::DPadDown,
+            Button::DPadLeft => gilrs::ev::Button::DPadLeft,
+            Button::DPadRight => gilrs::ev::Button::DPadRight,
+        }
+    }
+}
+
+/// Serializable mirror of the `winit::VirtualKeyCode` keys that can be bound
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    A,
+    B,
+    C,
+    D,
+    E,
+    F,
+    G,
+    H,
+    I,
+    J,
+    K,
+    L,
+    M,
+    N,
+    O,
+    P,
+    Q,
+    R,
+    S,
+    T,
+    U,
+    V,
+    W,
+    X,
+    Y,
+    Z,
+    Key0,
+    Key1,
+    Key2,
+    Key3,
+    Key4,
+    Key5,
+    Key6,
+    Key7,
+    Key8,
+    Key9,
+    Up,
+    Down,
+    Left,
+    Right,
+    Return,
+    Escape,
+    Space,
+    Back,
+    Tab,
+    LShift,
+    RShift,
+    LControl,
+    RControl,
+    LAlt,
+    RAlt,
+}
+
+impl Key {
+    pub fn from_winit(key: ::winit::VirtualKeyCode) -> Option<Self> {
+        match key {
+            ::winit::VirtualKeyCode::A => Some(Key::A),
+            ::winit::VirtualKeyCode::B => Some(Key::B),
+            ::winit::VirtualKeyCode::C => Some(Key::C),
+            ::winit::VirtualKeyCode::D => Some(Key::D),
+            ::winit::VirtualKeyCode::E => Some(Key::E),
+            ::winit::VirtualKeyCode::F => Some(Key::F),
+            ::winit::VirtualKeyCode::G => Some(Key::G),
+            ::winit::VirtualKeyCode::H => Some(Key::H),
+            ::winit::VirtualKeyCode::I => Some(Key::I),
+            ::winit::VirtualKeyCode::J => Some(Key::J),
+            ::winit::VirtualKeyCode::K => Some(Key::K),
+            ::winit::VirtualKeyCode::L => Some(Key::L),
+            ::winit::VirtualKeyCode::M => Some(Key::M),
+            ::winit::VirtualKeyCode::N => Some(Key::N),
+            ::winit::VirtualKeyCode::O => Some(Key::O),
+            ::winit::VirtualKeyCode::P => Some(Key::P),
+            ::winit::VirtualKeyCode::Q => Some(Key::Q),
+            ::winit::VirtualKeyCode::R => Some(Key::R),
+            ::winit::VirtualKeyCode::S => Some(Key::S),
+            ::winit::VirtualKeyCode::T => Some(Key::T),
+            ::winit::VirtualKeyCode::U => Some(Key::U),
+            ::winit::VirtualKeyCode::V => Some(Key::V),
+            ::winit::VirtualKeyCode::W => Some(Key::W),
+            ::winit::VirtualKeyCode::X => Some(Key::X),
+            ::winit::VirtualKeyCode::Y => Some(Key::Y),
+            ::winit::VirtualKeyCode::Z => Some(Key::Z),
+            ::winit::VirtualKeyCode::Key0 => Some(Key::Key0),
+            ::winit::VirtualKeyCode::Key1 => Some(Key::Key1),
+            ::winit::VirtualKeyCode::Key2 => Some(Key::Key2),
+            ::winit::VirtualKeyCode::Key3 => Some(Key::Key3),
+            ::winit::VirtualKeyCode::Key4 => Some(Key::Key4),
+            ::winit::VirtualKeyCode::Key5 => Some(Key::Key5),
+            ::winit::VirtualKeyCode::Key6 => Some(Key::Key6),
+            ::winit::VirtualKeyCode::Key7 => Some(Key::Key7),
+            ::winit::VirtualKeyCode::Key8 => Some(Key::Key8),
+            ::winit::VirtualKeyCode::Key9 => Some(Key::Key9),
+            ::winit::VirtualKeyCode::Up => Some(Key::Up),
+            ::winit::VirtualKeyCode::Down => Some(Key::Down),
+            ::winit::VirtualKeyCode::Left => Some(Key::Left),
+            ::winit::VirtualKeyCode::Right => Some(Key::Right),
+            ::winit::VirtualKeyCode::Return => Some(Key::Return),
+            ::winit::VirtualKeyCode::Escape => Some(Key::Escape),
+            ::winit::VirtualKeyCode::Space => Some(Key::Space),
+            ::winit::VirtualKeyCode::Back => Some(Key::Back),
+            ::winit::VirtualKeyCode::Tab => Some(Key::Tab),
+            ::winit::VirtualKeyCode::LShift => Some(Key::LShift),
+            ::winit::VirtualKeyCode::RShift => Some(Key::RShift),
+            ::winit::VirtualKeyCode::LControl => Some(Key::LControl),
+            ::winit::VirtualKeyCode::RControl => Some(Key::RControl),
+            ::winit::VirtualKeyCode::LAlt => Some(Key::LAlt),
+            ::winit::VirtualKeyCode::RAlt => Some(Key::RAlt),
+            _ => None,
+        }
+    }
+
+    pub fn to_winit(&self) -> ::winit::VirtualKeyCode {
+        match self {
+            Key::A => ::winit::VirtualKeyCode::A,
+            Key::B => ::winit::VirtualKeyCode::B,
+            Key::C => ::winit::VirtualKeyCode::C,
+            Key::D => ::winit::VirtualKeyCode::D,
+            Key::E => ::winit::VirtualKeyCode::E,
+            Key::F => ::winit::VirtualKeyCode::F,
+            Key::G => ::winit::VirtualKeyCode::G,
+            Key::H => ::winit::VirtualKeyCode::H,
+            Key::I => ::winit::VirtualKeyCode::I,
+            Key::J => ::winit::VirtualKeyCode::J,
+            Key::K => ::winit::VirtualKeyCode::K,
+            Key::L => ::winit::VirtualKeyCode::L,
+            Key::M => ::winit::VirtualKeyCode::M,
+            Key::N => ::winit::VirtualKeyCode::N,
+            Key::O => ::winit::VirtualKeyCode::O,
+            Key::P => ::winit::VirtualKeyCode::P,
+            Key::Q => ::winit::VirtualKeyCode::Q,
+            Key::R => ::winit::VirtualKeyCode::R,
+            Key::S => ::winit::VirtualKeyCode::S,
+            Key::T => ::winit::VirtualKeyCode::T,
+            Key::U => ::winit::VirtualKeyCode::U,
+            Key::V => ::winit::VirtualKeyCode::V,
+            Key::W => ::winit::VirtualKeyCode::W,
+            Key::X => ::winit::VirtualKeyCode::X,
+            Key::Y => ::winit::VirtualKeyCode::Y,
+            Key::Z => ::winit::VirtualKeyCode::Z,
+            Key::Key0 => ::winit::VirtualKeyCode::Key0,
+            Key::Key1 => ::winit::VirtualKeyCode::Key1,
+            Key::Key2 => ::winit::VirtualKeyCode::Key2,
+            Key::Key3 => ::winit::VirtualKeyCode::Key3,
+            Key::Key4 => ::winit::VirtualKeyCode::Key4,
+            Key::Key5 => ::winit::VirtualKeyCode::Key5,
+            Key::Key6 => ::winit::VirtualKeyCode::Key6,
+            Key::Key7 => ::winit::VirtualKeyCode::Key7,
+            Key::Key8 => ::winit::VirtualKeyCode::Key8,
+            Key::Key9 => ::winit::VirtualKeyCode::Key9,
+            Key::Up => ::winit::VirtualKeyCode::Up,
+            Key::Down => ::winit::VirtualKeyCode::Down,
+            Key::Left => ::winit::VirtualKeyCode::Left,
+            Key::Right => ::winit::VirtualKeyCode::Right,
+            Key::Return => ::winit::VirtualKeyCode::Return,
+            Key::Escape => ::winit::VirtualKeyCode::Escape,
+            Key::Space => ::winit::VirtualKeyCode::Space,
+            Key::Back => ::winit::VirtualKeyCode::Back,
+            Key::Tab => ::winit::VirtualKeyCode::Tab,
+            Key::LShift => ::winit::VirtualKeyCode::LShift,
+            Key::RShift => ::winit::VirtualKeyCode::RShift,
+            Key::LControl => ::winit::VirtualKeyCode::LControl,
+            Key::RControl => ::winit::VirtualKeyCode::RControl,
+            Key::LAlt => ::winit::VirtualKeyCode::LAlt,
+            Key::RAlt => ::winit::VirtualKeyCode::RAlt,
+        }
+    }
+}
+
+/// Menu and lobby actions, the D-pad always navigates menus
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Confirm,
+    Back,
+    /// Toggle one or two balls in the lobby
+    Option,
+    AddBot,
+    RemoveBot,
+    Start,
+}
+
+pub const ACTIONS: [Action; 6] = [
+    Action::Confirm,
+    Action::Back,
+    Action::Option,
+    Action::AddBot,
+    Action::RemoveBot,
+    Action::Start,
+];
+
+/// Menu navigation from the keyboard, unbound arrow keys, `Return` and `Escape` keep their default use
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MenuKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+}
+
+pub const MENU_KEYS: [MenuKey; 6] = [
+    MenuKey::Up,
+    MenuKey::Down,
+    MenuKey::Left,
+    MenuKey::Right,
+    MenuKey::Confirm,
+    MenuKey::Back,
+];
+
+/// Keys of the keyboard, stored once in preferences
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct KeyProfile {
+    /// Key of each menu key of `MENU_KEYS`
+    pub menu: [Key; 6],
+}
+
+impl Default for KeyProfile {
+    fn default() -> Self {
+        KeyProfile {
+            menu: [Key::Up, Key::Down, Key::Left, Key::Right, Key::Return, Key::Escape],
+        }
+    }
+}
+
+impl KeyProfile {
+    pub fn menu_key(&self, key: ::winit::VirtualKeyCode) -> Option<MenuKey> {
+        Key::from_winit(key)
+            .and_then(|key| self.menu.iter().position(|k| *k == key))
+            .or_else(|| KeyProfile::default().menu.iter().position(|k| k.to_winit() == key))
+            .map(|i| MENU_KEYS[i])
+    }
+
+    pub fn bind_menu(&mut self, index: usize, key: Key) {
+        bind(&mut self.menu, index, key);
+    }
+}
+
+/// Bind the value at the index, a binding already using the value takes the previous one so that
+/// no two bindings of the group share a value
+fn bind<T: Copy + PartialEq>(bindings: &mut [T], index: usize, value: T) {
+    if let Some(other) = bindings.iter().position(|binding| *binding == value) {
+        bindings[other] = bindings[index];
+    }
+    bindings[index] = value;
+}
+
+/// Bind the value like `bind`, a binding of the other group using the value takes the previous one
+/// too so that a button doesn't both jump and trigger an action
+fn bind_across<T: Copy + PartialEq>(bindings: &mut [T], index: usize, value: T, others: &mut [T]) {
+    if let Some(other) = others.iter().position(|binding| *binding == value) {
+        others[other] = bindings[index];
+    }
+    bind(bindings, index, value);
+}
+
+/// How stick directions become aim angles
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct AimConfig {
+    /// Stick length under which the stick is considered released
+    pub deadzone: f32,
+    /// Part of the remaining angle left to turn after 1/60 second, 0 turns instantly
+    pub smoothing: f32,
+    /// Maximal turn speed in radian per second
+    pub turn_rate: Option<f32>,
+    /// Number of directions the aim snaps to, 0 for free aim
+    pub snap: usize,
+}
+
+impl Default for AimConfig {
+    fn default() -> Self {
+        AimConfig {
+            deadzone: 0.1,
+            smoothing: 0.0,
+            turn_rate: None,
+            snap: 0,
+        }
+    }
+}
+
+impl AimConfig {
+    /// Snap the angle to the closest allowed direction
+    pub fn snap_angle(&self, angle: f32) -> f32 {
+        if self.snap == 0 {
+            return angle;
+        }
+        let step = 2.0 * ::std::f32::consts::PI / self.snap as f32;
+        (angle / step).round() * step
+    }
+
+    pub fn filter(&self) -> ::component::AimFilter {
+        ::component::AimFilter {
+            smoothing: self.smoothing,
+            turn_rate: self.turn_rate,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Profile {
+    /// Horizontal and vertical aim axes of each part of the gamepad
+    pub aim: [[Axis; 2]; 2],
+    /// Jump button of each part of the gamepad
+    pub jump: [Button; 2],
+    /// Button of each action of `ACTIONS`
+    pub actions: [Button; 6],
+    /// Aim last tuned by a player of this controller, new players start with it
+    pub aim_config: Option<AimConfig>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            aim: [
+                [Axis::LeftStickX, Axis::LeftStickY],
+                [Axis::RightStickX, Axis::RightStickY],
+            ],
+            jump: [Button::LeftTrigger, Button::RightTrigger],
+            actions: [
+                Button::South,
+                Button::East,
+                Button::West,
+                Button::North,
+                Button::Select,
+                Button::Start,
+            ],
+            aim_config: None,
+        }
+    }
+}
+
+impl Profile {
+    pub fn action(&self, button: gilrs::ev::Button) -> Option<Action> {
+        let button = Button::from_gilrs(button)?;
+        self.actions.iter()
+            .position(|b| *b == button)
+            .map(|i| ACTIONS[i])
+    }
+
+    pub fn bind_aim(&mut self, part: usize, stick: [Axis; 2]) {
+        bind(&mut self.aim, part, stick);
+    }
+
+    pub fn bind_jump(&mut self, part: usize, button: Button) {
+        bind_across(&mut self.jump, part, button, &mut self.actions);
+    }
+
+    pub fn bind_action(&mut self, index: usize, button: Button) {
+        bind_across(&mut self.actions, index, button, &mut self.jump);
+    }
+}
+
+/// Key of the profiles of a controller model in preferences
+pub fn uuid_key(uuid: &[u8; 16]) -> String {
+    uuid.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Profile of the gamepad, the default one if the gamepad is unknown
+pub fn profile(gamepad_id: usize, world: &specs::World) -> Profile {
+    let uuids = world.read_resource::<::resource::GamepadUuids>();
+    uuids.get(&gamepad_id)
+        .map(|uuid| world.read_resource::<::preferences::Preferences>().profile(uuid))
+        .unwrap_or_default()
+}
+
+/// Menu key bound to the key
+pub fn menu_key(key: ::winit::VirtualKeyCode, world: &specs::World) -> Option<MenuKey> {
+    world.read_resource::<::preferences::Preferences>().keys.menu_key(key)
+}
+
+/// Action bound to the button pressed in the event
+pub fn action(event: &gilrs::ev::Event, world: &specs::World) -> Option<Action> {
+    match event.event {
+        gilrs::ev::EventType::ButtonPressed(button, _) => profile(event.id, world).action(button),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn free_aim_is_not_snapped() {
+        let config = AimConfig::default();
+        assert_eq!(config.snap_angle(0.3), 0.3);
+    }
+
+    #[test]
+    fn aim_snaps_to_nearest_direction() {
+        let config = AimConfig { snap: 8, ..AimConfig::default() };
+        assert!((config.snap_angle(0.6) - PI / 4.0).abs() < 1e-6);
+        assert!((config.snap_angle(0.1)).abs() < 1e-6);
+        assert!((config.snap_angle(-2.5) + 3.0 * PI / 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn jump_bound_to_an_action_button_swaps_with_the_action() {
+        let mut profile = Profile::default();
+        profile.bind_jump(0, Button::Start);
+        assert_eq!(profile.jump, [Button::Start, Button::RightTrigger]);
+        assert_eq!(profile.actions[5], Button::LeftTrigger);
+    }
+
+    #[test]
+    fn action_bound_to_a_jump_button_swaps_with_the_jump() {
+        let mut profile = Profile::default();
+        profile.bind_action(0, Button::RightTrigger);
+        assert_eq!(profile.actions[0], Button::RightTrigger);
+        assert_eq!(profile.jump, [Button::LeftTrigger, Button::South]);
+    }
+}
@@ -0,0 +1,66 @@
+//! Ball-on-ball combat of the combat rule.
+//!
+//! `PhysicSystem` records the velocity of the other ball at each contact in `Impacts`,
+//! `CombatSystem` turns them into stomps and knockback and `ControlSystem` keeps stunned balls
+//! from jumping.
+
+/// Time in second a stomped ball can't jump
+pub const STUN_TIME: f32 = 1.0;
+/// Speed at which a ball must come down on another to stomp it
+pub const STOMP_SPEED: f32 = 0.5;
+/// Cosine of the largest angle from the vertical between two balls for a contact to be a stomp
+pub const STOMP_COS: f32 = 0.7;
+/// Relative speed above which an impact throws the balls apart
+pub const KNOCKBACK_SPEED: f32 = 1.2;
+/// Velocity given to a ball by each unit of relative speed above `KNOCKBACK_SPEED`
+pub const KNOCKBACK_FACTOR: f32 = 0.8;
+
+/// Whether the other ball came down on this one from above, `delta` goes from the other ball to
+/// this one and `velocity` is the velocity of the other ball relative to this one before the impact
+pub fn stomp(delta: ::na::Vector2<f32>, velocity: ::na::Vector2<f32>) -> bool {
+    // The y axis goes down
+    match delta.try_normalize(0.0001) {
+        Some(direction) => direction[1] >= STOMP_COS && velocity[1] >= STOMP_SPEED,
+        None => false,
+    }
+}
+
+/// Velocity to add to this ball pushing it away from the other one after a fast impact
+pub fn knockback(delta: ::na::Vector2<f32>, velocity: ::na::Vector2<f32>) -> Option<::na::Vector2<f32>> {
+    let speed = velocity.norm();
+    if speed <= KNOCKBACK_SPEED {
+        return None;
+    }
+    delta.try_normalize(0.0001).map(|direction| direction * (speed - KNOCKBACK_SPEED) * KNOCKBACK_FACTOR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::na::Vector2;
+
+    #[test]
+    fn stomp_from_above() {
+        // The other ball is above this one and falls on it
+        assert!(stomp(Vector2::new(0.0, 1.0), Vector2::new(0.0, STOMP_SPEED + 0.1)));
+    }
+
+    #[test]
+    fn no_stomp_when_slow_or_sideways() {
+        assert!(!stomp(Vector2::new(0.0, 1.0), Vector2::new(0.0, STOMP_SPEED - 0.1)));
+        assert!(!stomp(Vector2::new(1.0, 0.2), Vector2::new(0.0, 2.0)));
+        assert!(!stomp(Vector2::new(0.0, -1.0), Vector2::new(0.0, 2.0)));
+        assert!(!stomp(Vector2::new(0.0, 0.0), Vector2::new(0.0, 2.0)));
+    }
+
+    #[test]
+    fn no_knockback_under_speed() {
+        assert_eq!(knockback(Vector2::new(1.0, 0.0), Vector2::new(KNOCKBACK_SPEED, 0.0)), None);
+    }
+
+    #[test]
+    fn knockback_away_from_other_ball() {
+        let push = knockback(Vector2::new(-2.0, 0.0), Vector2::new(KNOCKBACK_SPEED + 1.0, 0.0)).unwrap();
+        assert!((push - Vector2::new(-KNOCKBACK_FACTOR, 0.0)).norm() < 1e-5, "push {}", push);
+    }
+}
@@ -1,13 +1,140 @@
 use specs::prelude::*;
 
-#[derive(Component)]
+/// Airjumps stored by the ball, restored by contacts and regenerated over time as the rules allow
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
 #[storage(VecStorage)]
-pub struct Airjump(pub bool);
+pub struct Airjump {
+    pub charges: u32,
+    /// Time in second since the last regenerated charge
+    pub regen: f32,
+}
+
+impl Airjump {
+    pub fn available(&self) -> bool {
+        self.charges > 0
+    }
+}
 
 #[derive(Component, Default)]
 #[storage(NullStorage)]
 pub struct AirjumpRestorer;
 
+/// Flag of the ball which is "it" in tag mode
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct It;
+
+/// Checkpoint sensor of the race mode with its index in `entity::CHECKPOINTS`
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct Checkpoint(pub usize);
+
+/// Power-up waiting to be picked up by a ball
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[storage(VecStorage)]
+pub struct Pickup {
+    pub power_up: ::power_up::PowerUp,
+    /// Index of its spot in `entity::PICKUP_SPOTS`
+    pub spot: usize,
+}
+
+/// Power-ups in effect on a ball with their remaining time in second
+#[derive(Deref, DerefMut, Component, Serialize, Deserialize, Clone, Debug, Default)]
+#[storage(VecStorage)]
+pub struct PowerUps(pub Vec<(::power_up::PowerUp, f32)>);
+
+/// Modifiers of the player of a ball balancing mixed-skill matches, see `handicap`
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[storage(VecStorage)]
+#[serde(default)]
+pub struct Handicap {
+    /// Factor of the radius of the ball
+    pub size: f32,
+    /// Factor of the jump velocity
+    pub jump: f32,
+    /// Airjumps the ball stores on top of the rules
+    pub extra_airjumps: u32,
+    /// Part of the angle between the aim and the nearest gong turned toward the gong
+    pub aim_assist: f32,
+}
+
+impl Default for Handicap {
+    fn default() -> Self {
+        Handicap {
+            size: 1.0,
+            jump: 1.0,
+            extra_airjumps: 0,
+            aim_assist: 0.0,
+        }
+    }
+}
+
+/// Contacts started during the last step with the velocity of the other body relative to this one
+/// before the step
+#[derive(Deref, DerefMut, Component, Clone, Debug, Default)]
+#[storage(VecStorage)]
+pub struct Impacts(pub Vec<(Entity, ::na::Vector2<f32>)>);
+
+/// Joint holding the body in the physic world, it must be removed before the body
+#[derive(Component)]
+#[storage(VecStorage)]
+pub struct Joint(pub ::nphysics2d::joint::ConstraintHandle);
+
+/// Flag of the hazards, which only exist with the hazards rule
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Hazard;
+
+/// Kinematic body moving back and forth along its points
+#[derive(Component, Clone, Debug)]
+#[storage(VecStorage)]
+pub struct Path {
+    pub points: Vec<[f32; 2]>,
+    /// Speed in unit per second
+    pub speed: f32,
+    /// Index of the point the body goes to
+    pub next: usize,
+}
+
+/// Acceleration given to the balls in the zone
+#[derive(Component, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct Wind(pub [f32; 2]);
+
+/// Flag of the spikes, they make balls respawn
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Spikes;
+
+/// Flag of the platforms balls go through from below and land on from above
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct OneWay;
+
+/// Flag of a ball to put back at a spawn point by the respawn system
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Respawn;
+
+/// Time in second during which spikes and eliminations spare the ball
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct Invulnerable(pub f32);
+
+/// Time in second during which a stomped ball can't jump
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug)]
+#[storage(VecStorage)]
+pub struct Stun(pub f32);
+
+/// Flag of the ground, the only restorer when balls don't restore airjumps
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Ground;
+
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+pub struct Gong;
+
 #[derive(Component)]
 #[storage(VecStorage)]
 pub struct Control {
@@ -16,6 +143,138 @@ pub struct Control {
     pub parts: [bool; 2],
 }
 
+/// What the player of the ball wants to do this step
+///
+/// It is filled by the game state from gamepads or by the bot system and consumed by the
+/// control system.
+#[derive(Component, Default, Clone)]
+#[storage(VecStorage)]
+pub struct Input {
+    /// Angle the ball must face
+    pub angle: Option<f32>,
+    /// Whether the jump button is held, the ball jumps when it gets pressed
+    pub jump: bool,
+    /// Pressure of the jump button between 0 and 1, digital buttons report 0 or 1 and `None`
+    /// means full strength
+    pub pressure: Option<f32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "easy" => Some(Difficulty::Easy),
+            "normal" => Some(Difficulty::Normal),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    /// Number of steps between two decisions
+    pub fn reaction(&self) -> usize {
+        match self {
+            Difficulty::Easy => 30,
+            Difficulty::Normal => 12,
+            Difficulty::Hard => 4,
+        }
+    }
+
+    /// Maximal error on aim angle in radian
+    pub fn aim_error(&self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.6,
+            Difficulty::Normal => 0.25,
+            Difficulty::Hard => 0.05,
+        }
+    }
+
+    /// Next difficulty if `harder` or previous one, saturates at the extremes
+    pub fn step(&self, harder: bool) -> Self {
+        match (self, harder) {
+            (Difficulty::Easy, true) => Difficulty::Normal,
+            (Difficulty::Normal, true) => Difficulty::Hard,
+            (Difficulty::Normal, false) => Difficulty::Easy,
+            (Difficulty::Hard, false) => Difficulty::Normal,
+            (difficulty, _) => *difficulty,
+        }
+    }
+}
+
+/// A ball controlled by the bot system
+#[derive(Component)]
+#[storage(VecStorage)]
+pub struct Bot {
+    pub difficulty: Difficulty,
+    /// Steps until next decision
+    pub cooldown: usize,
+    /// Steps the jump button stays held
+    pub hold: usize,
+    /// Xorshift state used for aim error
+    pub seed: u32,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty, seed: u32) -> Self {
+        Bot {
+            difficulty,
+            cooldown: 0,
+            hold: 0,
+            // Xorshift must not be seeded with zero
+            seed: seed.max(1),
+        }
+    }
+
+    /// Random number in [-1, 1]
+    pub fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed as f64 / u32::max_value() as f64 * 2.0 - 1.0) as f32
+    }
+}
+
+/// Limit how fast the ball turns toward its `Input` angle
+#[derive(Component, Clone, Debug)]
+#[storage(VecStorage)]
+pub struct AimFilter {
+    /// Part of the remaining angle left to turn after 1/60 second
+    pub smoothing: f32,
+    /// Maximal turn speed in radian per second
+    pub turn_rate: Option<f32>,
+}
+
+/// State of the jump button while it is held
+///
+/// Holding the button jumps only once: at the press with fixed jumps, after `ANALOG_WINDOW` or
+/// at the release with analog jumps and at the release with charged jumps.
+#[derive(Component, Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[storage(VecStorage)]
+pub struct JumpCharge {
+    /// Time in second the button has been held
+    pub time: f32,
+    /// Highest pressure since the press
+    pub peak: f32,
+    /// Whether this press already made the ball jump
+    pub jumped: bool,
+}
+
+/// Slot id of the player owning the ball
+#[derive(Component, Clone, Copy)]
+#[storage(VecStorage)]
+pub struct Player(pub usize);
+
+/// Color multiplied to the image
+#[derive(Component)]
+#[storage(VecStorage)]
+pub struct Tint(pub [f32; 4]);
+
 // pub struct CollisionSound {
 //     sound: TODO
 // }
@@ -44,12 +303,14 @@ impl RigidBody {
     ) -> Self {
         let body_handle =
             physic_world.add_rigid_body(position, local_inertia, local_center_of_mass);
-        {
-            let mut rigid_body = physic_world.rigid_body_mut(body_handle).unwrap();
-            rigid_body.set_status(status);
-            rigid_body
-                .activation_status_mut()
-                .set_deactivation_threshold(None);
+        match physic_world.rigid_body_mut(body_handle) {
+            Some(rigid_body) => {
+                rigid_body.set_status(status);
+                rigid_body
+                    .activation_status_mut()
+                    .set_deactivation_threshold(None);
+            }
+            None => ::error::log(::error::PhysicError::MissingBody(body_handle)),
         }
         bodies_map.insert(body_handle, entity);
 
@@ -66,20 +327,20 @@ impl RigidBody {
     pub fn get<'a>(
         &'a self,
         physic_world: &'a ::resource::PhysicWorld,
-    ) -> &'a ::nphysics2d::object::RigidBody<f32> {
+    ) -> Result<&'a ::nphysics2d::object::RigidBody<f32>, ::error::PhysicError> {
         physic_world
             .rigid_body(self.0)
-            .expect("Rigid body in specs does not exist in physic world")
+            .ok_or(::error::PhysicError::MissingBody(self.0))
     }
 
     #[inline]
     pub fn get_mut<'a>(
         &self,
         physic_world: &'a mut ::resource::PhysicWorld,
-    ) -> &'a mut ::nphysics2d::object::RigidBody<f32> {
+    ) -> Result<&'a mut ::nphysics2d::object::RigidBody<f32>, ::error::PhysicError> {
         physic_world
             .rigid_body_mut(self.0)
-            .expect("Rigid body in specs does not exist in physic world")
+            .ok_or(::error::PhysicError::MissingBody(self.0))
     }
 }
 
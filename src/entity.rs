@@ -3,6 +3,7 @@ use ncollide2d;
 use nphysics2d;
 use nphysics2d::volumetric::Volumetric;
 use specs::Builder;
+use specs::Join;
 
 pub const GRAVITY: f32 = 2.0;
 const RESTITUTION: f32 = 0.5;
@@ -16,34 +17,187 @@ const GONG_RADIUS: f32 = 0.2;
 const GONG_DENSITY: f32 = 0.01;
 const GONG_JOINT_LIMIT: f32 = 10.0;
 const GONG_POSITION_Y: f32 = -0.75;
+/// Distance from a pendulum gong to the point it swings from
+const GONG_PENDULUM_LENGTH: f32 = 0.25;
+/// Distance a moving gong goes on each side of its place and its speed
+const GONG_MOVE: f32 = 0.15;
+const GONG_SPEED: f32 = 0.3;
 
 const GROUND_POSITION_Y: f32 = 0.625;
 
-pub fn create_ball(gamepad_id: usize, gamepad_parts: [bool; 2], world: &mut specs::World) {
+/// Checkpoints of the race mode in order, the gong is the finish
+pub const CHECKPOINTS: [[f32; 2]; 4] = [[-0.7, 0.3], [0.7, 0.0], [-0.7, -0.3], [0.7, -0.55]];
+pub const CHECKPOINT_RADIUS: f32 = 0.1;
+
+/// Balls outside of those bounds are out of the arena: beside the walls, under the ground or above
+/// the top of the walls
+pub const ARENA_MIN: [f32; 2] = [-1.0, -10.0];
+pub const ARENA_MAX: [f32; 2] = [1.0, 1.0];
+/// Points where balls respawn, away from the hazards
+pub const SPAWN_POINTS: [[f32; 2]; 4] = [[-0.5, 0.0], [-0.15, 0.0], [0.15, 0.0], [0.4, 0.0]];
+/// Time in second a ball is invulnerable after it respawned
+pub const INVULNERABILITY_TIME: f32 = 2.0;
+/// Distance a body may travel in a physic step, under the radius of the smallest ball
+pub const SUBSTEP_DISTANCE: f32 = 0.02;
+pub const MAX_SUBSTEPS: usize = 8;
+
+/// Spots where power-ups appear
+pub const PICKUP_SPOTS: [[f32; 2]; 5] = [[-0.35, 0.4], [0.35, 0.4], [-0.3, -0.3], [0.3, -0.3], [0.0, -0.1]];
+pub const PICKUP_RADIUS: f32 = 0.05;
+
+/// Half extents of the moving and one-way platforms
+const PLATFORM_HALF_EXTENTS: [f32; 2] = [0.12, 0.016];
+const PLATFORM_SPEED: f32 = 0.2;
+/// Paths of the moving platforms
+const PLATFORM_PATHS: [[[f32; 2]; 2]; 2] = [[[-0.65, 0.1], [-0.3, 0.1]], [[0.6, 0.35], [0.6, -0.2]]];
+const ONE_WAY_POSITIONS: [[f32; 2]; 2] = [[-0.3, -0.1], [0.3, -0.1]];
+
+const BUMPER_RADIUS: f32 = 0.07;
+const BUMPER_RESTITUTION: f32 = 2.0;
+const BUMPER_POSITIONS: [[f32; 2]; 2] = [[-0.45, -0.45], [0.45, -0.45]];
+
+const SPIKES_HALF_EXTENTS: [f32; 2] = [0.12, 0.025];
+/// Spikes lie on the ground, away from where balls spawn
+const SPIKES_POSITIONS: [[f32; 2]; 2] = [
+    [-0.8, GROUND_POSITION_Y - 0.025],
+    [0.8, GROUND_POSITION_Y - 0.025],
+];
+
+const WIND_HALF_EXTENTS: [f32; 2] = [0.1, 0.25];
+const WIND_POSITION: [f32; 2] = [0.0, GROUND_POSITION_Y - 0.25];
+/// Acceleration of the wind, stronger than gravity so that balls go up
+const WIND_ACCELERATION: [f32; 2] = [0.0, -1.5 * GRAVITY];
+
+pub fn create_ball(
+    gamepad_id: usize,
+    gamepad_parts: [bool; 2],
+    handicap: ::component::Handicap,
+    world: &mut specs::World,
+) -> specs::Entity {
+    let entity = create_handicapped_ball_entity(handicap, world);
+    world.write_storage().insert(entity, ::component::Control {
+        gamepad_id,
+        parts: gamepad_parts,
+    });
+    entity
+}
+
+pub fn create_bot(
+    difficulty: ::component::Difficulty,
+    handicap: ::component::Handicap,
+    world: &mut specs::World,
+) -> specs::Entity {
+    let entity = create_handicapped_ball_entity(handicap, world);
+    world.write_storage().insert(entity, ::component::Bot::new(difficulty, entity.id()));
+    entity
+}
+
+/// Create the balls of a slot in the slot color
+pub fn create_slot_balls(slot: &::resource::Slot, world: &mut specs::World) -> Vec<specs::Entity> {
+    let balls = match slot.player {
+        ::resource::SlotPlayer::Gamepad(gamepad_id) if slot.two_balls => vec![
+            create_ball(gamepad_id, [true, false], slot.handicap, world),
+            create_ball(gamepad_id, [false, true], slot.handicap, world),
+        ],
+        ::resource::SlotPlayer::Gamepad(gamepad_id) => vec![create_ball(gamepad_id, [true, true], slot.handicap, world)],
+        ::resource::SlotPlayer::Bot(difficulty) => vec![create_bot(difficulty, slot.handicap, world)],
+    };
+    for ball in &balls {
+        world.write_storage().insert(*ball, ::component::Player(slot.id));
+        world.write_storage().insert(*ball, ::component::Tint(::resource::PLAYER_COLORS[slot.color]));
+    }
+    balls
+}
+
+/// Give the balls of a slot to another gamepad
+pub fn set_slot_gamepad(slot_id: usize, gamepad_id: usize, world: &specs::World) {
+    let players = world.read_storage::<::component::Player>();
+    let mut controls = world.write_storage::<::component::Control>();
+    for (_, control) in (&players, &mut controls).join().filter(|(player, _)| player.0 == slot_id) {
+        control.gamepad_id = gamepad_id;
+    }
+}
+
+/// Delete the balls of the slots
+pub fn delete_slot_balls(slot_ids: &[usize], world: &specs::World) {
+    let players = world.read_storage::<::component::Player>();
+    let entities = world.entities();
+    for (player, entity) in (&players, &*entities).join() {
+        if slot_ids.contains(&player.0) {
+            entities.delete(entity).unwrap();
+        }
+    }
+}
+
+/// Create a ball without any player controlling it, its `Input` must be written by the caller
+pub fn create_ball_entity(world: &mut specs::World) -> specs::Entity {
+    create_handicapped_ball_entity(::component::Handicap::default(), world)
+}
+
+/// Create a ball sized by the handicap of its player, which the ball keeps
+fn create_handicapped_ball_entity(handicap: ::component::Handicap, world: &mut specs::World) -> specs::Entity {
+    let shape = BallShape::new(&handicap);
     let entity = world.create_entity()
-        .with(::component::Image(BALL_RADIUS, ::Image::Ball))
-        .with(::component::Control {
-            gamepad_id,
-            parts: gamepad_parts,
-        })
-        .with(::component::Airjump(false))
+        .with(::component::Image(shape.radius, ::Image::Ball))
+        .with(::component::Input::default())
+        .with(::component::Airjump::default())
         .with(::component::AirjumpRestorer)
+        .with(::component::PowerUps::default())
+        .with(::component::Impacts::default())
+        .with(handicap)
         .with(::component::Contactor(vec![]))
         .build();
 
-    let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
+    add_ball_body(
+        entity,
+        shape,
+        ::na::Isometry2::new(::na::Vector2::new(0.0, 0.0), 0.0),
+        &mut world.write_storage(),
+        &mut world.write_resource(),
+        &mut world.write_resource(),
+    );
 
-    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Ball::new(BALL_RADIUS));
+    entity
+}
+
+/// Shape and material of the body of a ball
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BallShape {
+    pub radius: f32,
+    pub density: f32,
+    pub restitution: f32,
+}
+
+impl BallShape {
+    /// Shape of a ball of a player with the handicap
+    pub fn new(handicap: &::component::Handicap) -> Self {
+        BallShape {
+            radius: BALL_RADIUS * handicap.size,
+            density: BALL_DENSITY,
+            restitution: RESTITUTION,
+        }
+    }
+}
+
+fn add_ball_body<'a>(
+    entity: specs::Entity,
+    ball_shape: BallShape,
+    position: ::na::Isometry2<f32>,
+    bodies: &mut specs::WriteStorage<'a, ::component::RigidBody>,
+    physic_world: &mut ::resource::PhysicWorld,
+    bodies_map: &mut ::resource::BodiesMap,
+) -> ::component::RigidBody {
+    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Ball::new(ball_shape.radius));
 
     let body_handle = ::component::RigidBody::safe_insert(
         entity,
-        ::na::Isometry2::new(::na::Vector2::new(0.0, 0.0), 0.0),
-        shape.inertia(BALL_DENSITY),
+        position,
+        shape.inertia(ball_shape.density),
         shape.center_of_mass(),
         nphysics2d::object::BodyStatus::Dynamic,
-        &mut world.write_storage(),
-        &mut physic_world,
-        &mut world.write_resource(),
+        bodies,
+        physic_world,
+        bodies_map,
     );
 
     physic_world.add_collider(
@@ -51,16 +205,145 @@ pub fn create_ball(gamepad_id: usize, gamepad_parts: [bool; 2], world: &mut spec
         shape,
         body_handle.handle(),
         ::na::one(),
-        nphysics2d::object::Material::new(RESTITUTION, FRICTION),
+        nphysics2d::object::Material::new(ball_shape.restitution, FRICTION),
     );
+
+    body_handle
+}
+
+/// Replace the body of a ball by one of another shape at the same position and velocity.
+///
+/// The old body is removed at once instead of at maintain so that both don't collide, its
+/// contacts are forgotten and the new body reports them again on the next step.
+pub fn set_ball_shape<'a>(
+    entity: specs::Entity,
+    ball_shape: BallShape,
+    bodies: &mut specs::WriteStorage<'a, ::component::RigidBody>,
+    images: &mut specs::WriteStorage<'a, ::component::Image>,
+    contactors: &mut specs::WriteStorage<'a, ::component::Contactor>,
+    physic_world: &mut ::resource::PhysicWorld,
+    bodies_map: &mut ::resource::BodiesMap,
+) -> Result<(), ::error::PhysicError> {
+    let handle = match bodies.get(entity) {
+        Some(body) => body.handle(),
+        None => return Ok(()),
+    };
+    let (position, velocity) = {
+        let body = physic_world.rigid_body(handle).ok_or(::error::PhysicError::MissingBody(handle))?;
+        (*body.position(), *body.velocity())
+    };
+    physic_world.remove_bodies(&[handle]);
+    bodies_map.remove(&handle);
+
+    // Inserting over the old component doesn't retain it, it is already removed
+    let body = add_ball_body(entity, ball_shape, position, bodies, physic_world, bodies_map);
+    body.get_mut(physic_world)?.set_velocity(velocity);
+
+    if let Some(image) = images.get_mut(entity) {
+        image.0 = ball_shape.radius;
+    }
+    if let Some(contactor) = contactors.get_mut(entity) {
+        contactor.clear();
+    }
+    for contactor in (&mut *contactors).join() {
+        contactor.retain(|&contact| contact != entity);
+    }
+    Ok(())
+}
+
+/// Place balls in a row across the arena
+pub fn spread_balls(balls: &[specs::Entity], world: &specs::World) {
+    let bodies = world.read_storage::<::component::RigidBody>();
+    let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
+    for (i, entity) in balls.iter().enumerate() {
+        let x = (i as f32 + 1.0) / (balls.len() as f32 + 1.0) * 1.6 - 0.8;
+        let body = match bodies.get(*entity) {
+            Some(body) => body,
+            None => continue,
+        };
+        match body.get_mut(&mut physic_world) {
+            Ok(body) => body.set_position(::na::Isometry2::new(::na::Vector2::new(x, 0.0), 0.0)),
+            Err(error) => ::error::log(error),
+        }
+    }
+}
+
+/// Create the gongs of the rules spread across the top of the arena
+pub fn create_gongs(rules: &::rules::GongRules, world: &mut specs::World) {
+    for i in 0..rules.count {
+        let x = (i as f32 + 1.0) / (rules.count as f32 + 1.0) * 2.0 - 1.0;
+        create_gong([x, GONG_POSITION_Y], rules.mount, world);
+    }
 }
 
-pub fn create_gong(world: &mut specs::World) {
+/// Replace the gongs by the ones of the `Rules` resource
+pub fn reset_gongs(world: &mut specs::World) {
+    {
+        let gongs = world.read_storage::<::component::Gong>();
+        let joints = world.read_storage::<::component::Joint>();
+        let entities = world.entities();
+        let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
+        for (entity, _) in (&*entities, &gongs).join() {
+            if let Some(joint) = joints.get(entity) {
+                physic_world.remove_constraint(joint.0);
+            }
+            if let Err(error) = entities.delete(entity) {
+                ::error::log(error);
+            }
+        }
+    }
+    ::safe_maintain(world);
+
+    let rules = world.read_resource::<::rules::Rules>().gong.clone();
+    create_gongs(&rules, world);
+}
+
+/// Replace the `Rules` resource, the hazards and the gongs are recreated if their rules changed
+pub fn set_rules(rules: &::rules::Rules, world: &mut specs::World) {
+    let previous = world.read_resource::<::rules::Rules>().clone();
+    if previous == *rules {
+        return;
+    }
+    *world.write_resource::<::rules::Rules>() = rules.clone();
+    if previous.hazards != rules.hazards {
+        {
+            let hazards = world.read_storage::<::component::Hazard>();
+            let entities = world.entities();
+            for (_, entity) in (&hazards, &*entities).join() {
+                if let Err(error) = entities.delete(entity) {
+                    ::error::log(error);
+                }
+            }
+        }
+        ::safe_maintain(world);
+        if rules.hazards {
+            create_hazards(world);
+        }
+    }
+    if previous.gong != rules.gong {
+        reset_gongs(world);
+    }
+}
+
+pub fn create_gong(position: [f32; 2], mount: ::rules::GongMount, world: &mut specs::World) {
     let entity = world.create_entity()
         .with(::component::Image(GONG_RADIUS, ::Image::Gong))
+        .with(::component::Gong)
+        .with(::component::Impacts::default())
         .build();
 
-    let position = ::na::Point2::new(0.0, GONG_POSITION_Y);
+    let status = if mount == ::rules::GongMount::Moving {
+        world.write_storage().insert(entity, ::component::Path {
+            points: vec![[position[0] - GONG_MOVE, position[1]], [position[0] + GONG_MOVE, position[1]]],
+            speed: GONG_SPEED,
+            next: 0,
+        });
+        nphysics2d::object::BodyStatus::Kinematic
+    } else {
+        nphysics2d::object::BodyStatus::Dynamic
+    };
+
+    let position = ::na::Point2::new(position[0], position[1]);
 
     let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
 
@@ -71,21 +354,44 @@ pub fn create_gong(world: &mut specs::World) {
         ::na::Isometry2::new(position.coords, 0.0),
         shape.inertia(GONG_DENSITY),
         shape.center_of_mass(),
-        nphysics2d::object::BodyStatus::Dynamic,
+        status,
         &mut world.write_storage(),
         &mut physic_world,
         &mut world.write_resource(),
     );
 
-    physic_world.add_constraint(
-        nphysics2d::joint::MouseConstraint::new(
-            nphysics2d::object::BodyHandle::ground(),
-            body_handle.handle(),
-            position,
-            nphysics2d::math::Point::new(0.0, 0.0),
-            GONG_JOINT_LIMIT,
-        )
-    );
+    let joint = match mount {
+        ::rules::GongMount::Spring => Some(physic_world.add_constraint(
+            nphysics2d::joint::MouseConstraint::new(
+                nphysics2d::object::BodyHandle::ground(),
+                body_handle.handle(),
+                position,
+                nphysics2d::math::Point::new(0.0, 0.0),
+                GONG_JOINT_LIMIT,
+            )
+        )),
+        ::rules::GongMount::Pendulum => Some(physic_world.add_constraint(
+            nphysics2d::joint::RevoluteConstraint::new(
+                nphysics2d::object::BodyHandle::ground(),
+                body_handle.handle(),
+                ::na::Point2::new(position[0], position[1] - GONG_PENDULUM_LENGTH),
+                nphysics2d::math::Point::new(0.0, -GONG_PENDULUM_LENGTH),
+            )
+        )),
+        ::rules::GongMount::Rail => Some(physic_world.add_constraint(
+            nphysics2d::joint::PrismaticConstraint::new(
+                nphysics2d::object::BodyHandle::ground(),
+                body_handle.handle(),
+                position,
+                ::na::Vector2::x_axis(),
+                nphysics2d::math::Point::new(0.0, 0.0),
+            )
+        )),
+        ::rules::GongMount::Moving => None,
+    };
+    if let Some(joint) = joint {
+        world.write_storage().insert(entity, ::component::Joint(joint));
+    }
 
     physic_world.add_collider(
         0.0,
@@ -96,16 +402,20 @@ pub fn create_gong(world: &mut specs::World) {
     );
 }
 
-pub fn create_ground(world: &mut specs::World) {
+pub fn create_ground(world: &mut specs::World) -> Result<(), ::error::PhysicError> {
+    if world.read_resource::<::resource::BodiesMap>()
+        .contains_key(&::nphysics2d::object::BodyHandle::ground())
+    {
+        return Err(::error::PhysicError::GroundAlreadyInserted);
+    }
+
     let entity = world.create_entity()
         .with(::component::AirjumpRestorer)
+        .with(::component::Ground)
         .build();
 
     let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
     let mut bodies_map = world.write_resource::<::resource::BodiesMap>();
-    if bodies_map.contains_key(&::nphysics2d::object::BodyHandle::ground()) {
-        panic!("Only one ground can be inserted into world");
-    }
     bodies_map.insert(::nphysics2d::object::BodyHandle::ground(), entity);
 
     let shape = ncollide2d::shape::ShapeHandle::new(
@@ -122,6 +432,210 @@ pub fn create_ground(world: &mut specs::World) {
         ::na::one(),
         nphysics2d::object::Material::new(RESTITUTION, FRICTION),
     );
+
+    Ok(())
+}
+
+/// Create the checkpoint sensors, they don't collide and are only drawn in race mode
+pub fn create_checkpoints(world: &mut specs::World) {
+    for (i, position) in CHECKPOINTS.iter().enumerate() {
+        let entity = world.create_entity()
+            .with(::component::Checkpoint(i))
+            .build();
+
+        let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
+
+        let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Ball::new(CHECKPOINT_RADIUS));
+
+        let body_handle = ::component::RigidBody::safe_insert(
+            entity,
+            ::na::Isometry2::new(::na::Vector2::new(position[0], position[1]), 0.0),
+            nphysics2d::algebra::Inertia2::zero(),
+            ::na::Point2::new(0.0, 0.0),
+            nphysics2d::object::BodyStatus::Static,
+            &mut world.write_storage(),
+            &mut physic_world,
+            &mut world.write_resource(),
+        );
+
+        physic_world.add_sensor(
+            shape,
+            body_handle.handle(),
+            ::na::one(),
+        );
+    }
+}
+
+/// Create a pickup sensor from a system, it is deleted when a ball takes it
+pub fn create_pickup<'a>(
+    power_up: ::power_up::PowerUp,
+    spot: usize,
+    entities: &specs::Entities<'a>,
+    pickups: &mut specs::WriteStorage<'a, ::component::Pickup>,
+    images: &mut specs::WriteStorage<'a, ::component::Image>,
+    bodies: &mut specs::WriteStorage<'a, ::component::RigidBody>,
+    physic_world: &mut ::resource::PhysicWorld,
+    bodies_map: &mut ::resource::BodiesMap,
+) {
+    let entity = entities.create();
+    pickups.insert(entity, ::component::Pickup { power_up, spot });
+    images.insert(entity, ::component::Image(PICKUP_RADIUS, power_up.image()));
+
+    let position = PICKUP_SPOTS[spot];
+    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Ball::new(PICKUP_RADIUS));
+
+    let body_handle = ::component::RigidBody::safe_insert(
+        entity,
+        ::na::Isometry2::new(::na::Vector2::new(position[0], position[1]), 0.0),
+        nphysics2d::algebra::Inertia2::zero(),
+        ::na::Point2::new(0.0, 0.0),
+        nphysics2d::object::BodyStatus::Static,
+        bodies,
+        physic_world,
+        bodies_map,
+    );
+
+    physic_world.add_sensor(
+        shape,
+        body_handle.handle(),
+        ::na::one(),
+    );
+}
+
+/// Create the hazards of the arena, they are deleted with the balls when a round starts
+pub fn create_hazards(world: &mut specs::World) {
+    for path in PLATFORM_PATHS.iter() {
+        create_platform(path.to_vec(), world);
+    }
+    for position in ONE_WAY_POSITIONS.iter() {
+        create_one_way(*position, world);
+    }
+    for position in BUMPER_POSITIONS.iter() {
+        create_bumper(*position, world);
+    }
+    for position in SPIKES_POSITIONS.iter() {
+        create_spikes(*position, world);
+    }
+    create_wind(WIND_POSITION, WIND_ACCELERATION, world);
+}
+
+/// Create a kinematic platform moving back and forth along the points, starting at the first one
+pub fn create_platform(points: Vec<[f32; 2]>, world: &mut specs::World) {
+    let position = points[0];
+    let entity = world.create_entity()
+        .with(::component::Image(PLATFORM_HALF_EXTENTS[1], ::Image::Platform))
+        .with(::component::Hazard)
+        .with(::component::AirjumpRestorer)
+        .with(::component::Path {
+            points,
+            speed: PLATFORM_SPEED,
+            next: 0,
+        })
+        .build();
+
+    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Cuboid::new(
+        ::na::Vector2::new(PLATFORM_HALF_EXTENTS[0], PLATFORM_HALF_EXTENTS[1]),
+    ));
+    add_hazard_body(
+        entity,
+        position,
+        shape,
+        nphysics2d::object::BodyStatus::Kinematic,
+        Some(nphysics2d::object::Material::new(RESTITUTION, FRICTION)),
+        world,
+    );
+}
+
+/// Create a platform balls go through from below, see `OneWaySystem`
+pub fn create_one_way(position: [f32; 2], world: &mut specs::World) {
+    let entity = world.create_entity()
+        .with(::component::Image(PLATFORM_HALF_EXTENTS[1], ::Image::OneWay))
+        .with(::component::Hazard)
+        .with(::component::OneWay)
+        .build();
+
+    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Cuboid::new(
+        ::na::Vector2::new(PLATFORM_HALF_EXTENTS[0], PLATFORM_HALF_EXTENTS[1]),
+    ));
+    add_hazard_body(entity, position, shape, nphysics2d::object::BodyStatus::Static, None, world);
+}
+
+/// Create a bumper, its restitution throws balls back faster than they came
+pub fn create_bumper(position: [f32; 2], world: &mut specs::World) {
+    let entity = world.create_entity()
+        .with(::component::Image(BUMPER_RADIUS, ::Image::Bumper))
+        .with(::component::Hazard)
+        .build();
+
+    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Ball::new(BUMPER_RADIUS));
+    add_hazard_body(
+        entity,
+        position,
+        shape,
+        nphysics2d::object::BodyStatus::Static,
+        Some(nphysics2d::object::Material::new(BUMPER_RESTITUTION, FRICTION)),
+        world,
+    );
+}
+
+/// Create spikes making the balls touching them respawn, see `SpikeSystem`
+pub fn create_spikes(position: [f32; 2], world: &mut specs::World) {
+    let entity = world.create_entity()
+        .with(::component::Image(SPIKES_HALF_EXTENTS[1], ::Image::Spikes))
+        .with(::component::Hazard)
+        .with(::component::Spikes)
+        .build();
+
+    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Cuboid::new(
+        ::na::Vector2::new(SPIKES_HALF_EXTENTS[0], SPIKES_HALF_EXTENTS[1]),
+    ));
+    add_hazard_body(entity, position, shape, nphysics2d::object::BodyStatus::Static, None, world);
+}
+
+/// Create a zone accelerating the balls inside, see `WindSystem`
+pub fn create_wind(position: [f32; 2], acceleration: [f32; 2], world: &mut specs::World) {
+    let entity = world.create_entity()
+        .with(::component::Image(WIND_HALF_EXTENTS[1], ::Image::Wind))
+        .with(::component::Hazard)
+        .with(::component::Wind(acceleration))
+        .build();
+
+    let shape = ncollide2d::shape::ShapeHandle::new(ncollide2d::shape::Cuboid::new(
+        ::na::Vector2::new(WIND_HALF_EXTENTS[0], WIND_HALF_EXTENTS[1]),
+    ));
+    add_hazard_body(entity, position, shape, nphysics2d::object::BodyStatus::Static, None, world);
+}
+
+/// Add a body without mass to a hazard, with a collider of the material or a sensor without material
+fn add_hazard_body(
+    entity: specs::Entity,
+    position: [f32; 2],
+    shape: ncollide2d::shape::ShapeHandle<f32>,
+    status: nphysics2d::object::BodyStatus,
+    material: Option<nphysics2d::object::Material<f32>>,
+    world: &mut specs::World,
+) {
+    let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
+
+    let body_handle = ::component::RigidBody::safe_insert(
+        entity,
+        ::na::Isometry2::new(::na::Vector2::new(position[0], position[1]), 0.0),
+        nphysics2d::algebra::Inertia2::zero(),
+        ::na::Point2::new(0.0, 0.0),
+        status,
+        &mut world.write_storage(),
+        &mut physic_world,
+        &mut world.write_resource(),
+    );
+
+    match material {
+        Some(material) => {
+            physic_world.add_collider(0.0, shape, body_handle.handle(), ::na::one(), material);
+        }
+        None => {
+            physic_world.add_sensor(shape, body_handle.handle(), ::na::one());
+        }
+    }
 }
 
 pub fn create_walls(world: &mut specs::World) {
@@ -0,0 +1,148 @@
+use std::error;
+use std::fmt;
+use std::io;
+use serde_json;
+
+/// Desynchronisation between specs storages, the bodies map and the nphysics world.
+///
+/// None of those are fatal: the faulty body or contact is skipped and the error is logged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PhysicError {
+    /// A handle stored in specs or in the bodies map has no body in the physic world
+    MissingBody(::nphysics2d::object::BodyHandle),
+    /// A body of the physic world is not mapped to any entity
+    UnmappedBody(::nphysics2d::object::BodyHandle),
+    /// A contact event refers to a collision object that doesn't exist anymore
+    MissingCollisionObject(::ncollide2d::world::CollisionObjectHandle),
+    /// The ground body handle is already mapped to an entity
+    GroundAlreadyInserted,
+}
+
+impl fmt::Display for PhysicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PhysicError::MissingBody(handle) => {
+                write!(f, "body {:?} does not exist in physic world", handle)
+            }
+            PhysicError::UnmappedBody(handle) => {
+                write!(f, "body {:?} is not mapped to any entity", handle)
+            }
+            PhysicError::MissingCollisionObject(handle) => {
+                write!(f, "collision object {:?} does not exist in collision world", handle)
+            }
+            PhysicError::GroundAlreadyInserted => {
+                write!(f, "only one ground can be inserted into world")
+            }
+        }
+    }
+}
+
+impl error::Error for PhysicError {
+    fn description(&self) -> &str {
+        "physic world and specs world are out of sync"
+    }
+}
+
+/// Failure to save or load a snapshot file
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "snapshot file: {}", error),
+            SnapshotError::Json(error) => write!(f, "snapshot format: {}", error),
+        }
+    }
+}
+
+impl error::Error for SnapshotError {
+    fn description(&self) -> &str {
+        "snapshot could not be saved or loaded"
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        SnapshotError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(error: serde_json::Error) -> Self {
+        SnapshotError::Json(error)
+    }
+}
+
+/// Failure of a peer to peer session, the session stops on it
+#[derive(Debug)]
+pub enum NetError {
+    Io(io::Error),
+    /// The peers simulated different states for the frame with the same inputs
+    Desync { frame: u32 },
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetError::Io(error) => write!(f, "network: {}", error),
+            NetError::Desync { frame } => write!(f, "peers desynchronized at frame {}", frame),
+        }
+    }
+}
+
+impl error::Error for NetError {
+    fn description(&self) -> &str {
+        "peer to peer session failed"
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(error: io::Error) -> Self {
+        NetError::Io(error)
+    }
+}
+
+#[derive(Debug)]
+pub enum PreferencesError {
+    /// Neither the config directory nor the home directory is known
+    NoConfigDirectory,
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for PreferencesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PreferencesError::NoConfigDirectory => write!(f, "preferences: no config directory"),
+            PreferencesError::Io(error) => write!(f, "preferences file: {}", error),
+            PreferencesError::Json(error) => write!(f, "preferences format: {}", error),
+        }
+    }
+}
+
+impl error::Error for PreferencesError {
+    fn description(&self) -> &str {
+        "preferences could not be saved or loaded"
+    }
+}
+
+impl From<io::Error> for PreferencesError {
+    fn from(error: io::Error) -> Self {
+        PreferencesError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for PreferencesError {
+    fn from(error: serde_json::Error) -> Self {
+        PreferencesError::Json(error)
+    }
+}
+
+/// Log an error that the game recovers from.
+pub fn log<E: error::Error>(error: E) {
+    eprintln!("error: {}", error);
+}
@@ -10,6 +10,8 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use alga::general::SubsetOf;
 
+const WHITE: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
 #[derive(Debug, Clone)]
 struct Vertex {
     position: [f32; 2],
@@ -34,6 +36,7 @@ pub struct Graphics {
     sets_pool: vulkano::descriptor::descriptor_set::FixedSizeDescriptorSetsPool<Arc<vulkano::descriptor::pipeline_layout::PipelineLayoutAbstract + Sync + Send>>,
     dimensions: [u32; 2],
     sampler: Arc<vulkano::sampler::Sampler>,
+    fullscreen: bool,
 }
 
 fn mat4(mat3: [[f32; 3]; 3]) -> [[f32; 4]; 4] {
@@ -46,14 +49,14 @@ fn mat4(mat3: [[f32; 3]; 3]) -> [[f32; 4]; 4] {
 }
 
 impl Graphics {
-    pub fn new(events_loop: &winit::EventsLoop) -> Self {
+    pub fn new(events_loop: &winit::EventsLoop, fullscreen: bool) -> Self {
         let extensions = vulkano_win::required_extensions();
         let instance = vulkano::instance::Instance::new(None, &extensions, &[])
             .expect("failed to create instance");
 
         let surface = winit::WindowBuilder::new()
             .with_title("Airjump Multi")
-            .with_fullscreen(Some(events_loop.get_primary_monitor()))
+            .with_fullscreen(if fullscreen { Some(events_loop.get_primary_monitor()) } else { None })
             .build_vk_surface(&events_loop, instance.clone())
             .unwrap();
 
@@ -227,7 +230,23 @@ impl Graphics {
             textures,
             swapchain,
             surface,
+            fullscreen,
+        }
+    }
+
+    /// Switch the window to fullscreen on its current monitor or back to windowed mode
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        if fullscreen == self.fullscreen {
+            return;
         }
+        let monitor = if fullscreen {
+            Some(self.surface.window().get_current_monitor())
+        } else {
+            None
+        };
+        self.surface.window().set_fullscreen(monitor);
+        self.fullscreen = fullscreen;
+        self.recreate_swapchain = true;
     }
 
     pub fn render(&mut self, world: &mut specs::World) {
@@ -337,11 +356,12 @@ impl Graphics {
             )
             .unwrap();
 
-        let mut draw_image = |image, mut trans: ::na::Transform2<f32>, z: f32, cb: vulkano::command_buffer::AutoCommandBufferBuilder<vulkano::command_buffer::pool::standard::StandardCommandPoolBuilder>| {
+        let mut draw_image = |image, mut trans: ::na::Transform2<f32>, z: f32, color: [f32; 4], cb: vulkano::command_buffer::AutoCommandBufferBuilder<vulkano::command_buffer::pool::standard::StandardCommandPoolBuilder>| {
             let ref texture = self.textures[&image];
             trans[(0, 0)] *= texture.0 as f32/ texture.1 as f32;
             let trans = self.transform_buffer_pool.next(vs::ty::Transform {
                 trans: mat4(trans.unwrap().into()),
+                color,
                 z,
             }).unwrap();
 
@@ -368,22 +388,41 @@ impl Graphics {
         let mut trans: ::na::Transform2<f32> = ::na::one();
         trans[(0, 0)] *= 2.0;
         trans[(1, 1)] *= 2.0;
-        cb = draw_image(::Image::Wallpaper, trans, 0.0, cb);
+        cb = draw_image(::Image::Wallpaper, trans, 0.0, WHITE, cb);
 
+        let entities = world.entities();
         let bodies = world.read_storage::<::component::RigidBody>();
         let images = world.read_storage::<::component::Image>();
+        let tints = world.read_storage::<::component::Tint>();
         let physic_world = world.read_resource::<::resource::PhysicWorld>();
-        for (image, body) in (&images, &bodies).join() {
+        for (entity, image, body) in (&*entities, &images, &bodies).join() {
+            let body = match body.get(&physic_world) {
+                Ok(body) => body,
+                Err(error) => {
+                    ::error::log(error);
+                    continue;
+                }
+            };
             let mut trans: ::na::Transform2<f32> = ::na::Similarity2::from_isometry(
-                body.get(&physic_world).position(),
+                body.position(),
                 image.0*2.0,
             ).to_superset();
-            cb = draw_image(image.1, trans, 1.0, cb);
+            let color = tints.get(entity).map(|tint| tint.0).unwrap_or(WHITE);
+            cb = draw_image(image.1, trans, 1.0, color, cb);
         }
 
         if let Some(image) = world.write_resource::<::resource::DrawImage>().take() {
             let mut trans: ::na::Transform2<f32> = ::na::one();
-            cb = draw_image(image, trans, 1.0, cb);
+            cb = draw_image(image, trans, 1.0, WHITE, cb);
+        }
+
+        for sprite in world.write_resource::<::resource::Sprites>().drain(..) {
+            let trans: ::na::Transform2<f32> = ::na::Similarity2::new(
+                ::na::Vector2::new(sprite.position[0], sprite.position[1]),
+                0.0,
+                sprite.height,
+            ).to_superset();
+            cb = draw_image(sprite.image, trans, 1.0, sprite.color, cb);
         }
 
         cb.end_render_pass()
@@ -402,8 +441,11 @@ mod vs {
 layout(location = 0) in vec2 position;
 layout(location = 0) out vec2 tex_coords;
 
+layout(location = 1) out vec4 color;
+
 layout(set = 0, binding = 0) uniform Transform {
     mat4 trans;
+    vec4 color;
     float z;
 } transform;
 
@@ -416,6 +458,7 @@ void main() {
     // // https://matthewwellings.com/blog/the-new-vulkan-coordinate-system/
     // gl_Position.y = -gl_Position.y;
     tex_coords = position + vec2(0.5);
+    color = transform.color;
 }
 "]
     struct _Dummy;
@@ -428,12 +471,13 @@ mod fs {
 #version 450
 
 layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 color;
 layout(location = 0) out vec4 f_color;
 
 layout(set = 0, binding = 1) uniform sampler2D tex;
 
 void main() {
-    f_color = texture(tex, tex_coords);
+    f_color = texture(tex, tex_coords) * color;
 }
 "]
     struct _Dummy;
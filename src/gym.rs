@@ -0,0 +1,336 @@
+//! Headless environment to train agents, speaking JSON lines over stdin/stdout or a local
+//! TCP socket (`--gym-port <port>`).
+//!
+//! Requests:
+//! * `{"type": "reset", "agents": 2, "bots": ["hard"], "reward": {...}, "rules": {...}}`: recreate
+//!   the world with the agents, bots and match rules, all fields are optional
+//! * `{"type": "step", "actions": [{"aim": [x, y], "jump": true, "pressure": 0.5}, ...]}`: one action
+//!   per agent, `aim` is in world coordinates (y goes down), `aim` and `pressure` are optional
+//! * `{"type": "close"}`
+//!
+//! Each request is answered by a `Response` line, or `{"error": "..."}` if it couldn't be parsed.
+
+use specs;
+use specs::Join;
+use serde_json;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use snapshot::BodyState;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct RewardConfig {
+    /// Given to an agent when its ball starts to touch a gong
+    pub gong: f32,
+    /// Given to every agent at each step
+    pub step: f32,
+    /// Whether the episode ends when a ball touches a gong
+    pub end_on_gong: bool,
+    /// Maximal number of steps of an episode
+    pub max_steps: usize,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        RewardConfig {
+            gong: 1.0,
+            step: 0.0,
+            end_on_gong: true,
+            max_steps: 60 * 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    Reset {
+        #[serde(default)]
+        agents: Option<usize>,
+        #[serde(default)]
+        bots: Vec<::component::Difficulty>,
+        #[serde(default)]
+        reward: Option<RewardConfig>,
+        #[serde(default)]
+        rules: Option<::rules::Rules>,
+    },
+    Step {
+        actions: Vec<Action>,
+    },
+    Close,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct Action {
+    pub aim: Option<[f32; 2]>,
+    pub jump: bool,
+    /// Trigger pressure between 0 and 1 for analog jumps
+    pub pressure: Option<f32>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct BallObservation {
+    pub body: BodyState,
+    /// Whether an airjump is available
+    pub airjump: bool,
+    pub airjumps: u32,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct GongObservation {
+    pub body: BodyState,
+    /// Whether a ball started to touch the gong during the last step
+    pub hit: bool,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Observation {
+    /// Agents first in their order, then bots
+    pub balls: Vec<BallObservation>,
+    pub gongs: Vec<GongObservation>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Response {
+    pub observation: Observation,
+    /// One reward per agent
+    pub reward: Vec<f32>,
+    pub done: bool,
+    pub step: usize,
+}
+
+pub struct Env<'a, 'b> {
+    world: specs::World,
+    dispatcher: specs::Dispatcher<'a, 'b>,
+    agents: Vec<specs::Entity>,
+    bots: Vec<specs::Entity>,
+    reward: RewardConfig,
+    step: usize,
+    /// For each ball, whether it was touching a gong after the previous step
+    touching: Vec<bool>,
+}
+
+impl<'a, 'b> Env<'a, 'b> {
+    pub fn new() -> Self {
+        let mut env = Env {
+            world: ::create_world(),
+            dispatcher: ::create_update_dispatcher(),
+            agents: vec![],
+            bots: vec![],
+            reward: RewardConfig::default(),
+            step: 0,
+            touching: vec![],
+        };
+        env.reset(2, vec![], RewardConfig::default(), ::rules::Rules::default());
+        env
+    }
+
+    pub fn reset(
+        &mut self,
+        agents: usize,
+        bots: Vec<::component::Difficulty>,
+        reward: RewardConfig,
+        mut rules: ::rules::Rules,
+    ) -> Response {
+        rules.clamp();
+        self.world = ::create_world();
+        if rules.hazards {
+            ::entity::create_hazards(&mut self.world);
+        }
+        self.world.add_resource(rules);
+        ::entity::reset_gongs(&mut self.world);
+        self.dispatcher = ::create_update_dispatcher();
+        self.reward = reward;
+        self.step = 0;
+
+        self.agents = (0..agents)
+            .map(|_| ::entity::create_ball_entity(&mut self.world))
+            .collect();
+        self.bots = bots.into_iter()
+            .map(|difficulty| ::entity::create_bot(difficulty, ::component::Handicap::default(), &mut self.world))
+            .collect();
+
+        // Spread balls so that episodes are deterministic
+        let balls = self.agents.iter().chain(self.bots.iter()).cloned().collect::<Vec<_>>();
+        ::entity::spread_balls(&balls, &self.world);
+
+        self.touching = self.touching_gong();
+        Response {
+            observation: self.observe(vec![false; self.gong_count()]),
+            reward: vec![0.0; self.agents.len()],
+            done: false,
+            step: self.step,
+        }
+    }
+
+    pub fn step(&mut self, actions: &[Action]) -> Response {
+        {
+            let mut inputs = self.world.write_storage::<::component::Input>();
+            for (i, entity) in self.agents.iter().enumerate() {
+                let action = actions.get(i).cloned().unwrap_or_default();
+                if let Some(input) = inputs.get_mut(*entity) {
+                    input.angle = action.aim
+                        .map(|aim| ::na::Vector2::new(aim[0], aim[1]))
+                        .and_then(|aim| aim.try_normalize(0.0001))
+                        .map(|aim| aim[1].atan2(aim[0]));
+                    input.jump = action.jump;
+                    input.pressure = action.pressure;
+                }
+            }
+        }
+
+        self.world.write_resource::<::resource::UpdateTime>().0 = ::FIXED_STEP;
+        self.dispatcher.dispatch(&mut self.world.res);
+        ::safe_maintain(&mut self.world);
+        self.step += 1;
+
+        let touching = self.touching_gong();
+        let started = touching.iter()
+            .zip(self.touching.iter())
+            .map(|(&now, &before)| now && !before)
+            .collect::<Vec<_>>();
+        self.touching = touching;
+
+        let mut reward = vec![self.reward.step; self.agents.len()];
+        for (agent_reward, _) in reward.iter_mut().zip(started.iter()).filter(|&(_, &started)| started) {
+            *agent_reward += self.reward.gong;
+        }
+        let hit = started.iter().any(|&started| started);
+
+        Response {
+            observation: self.observe(self.gong_hits(&started)),
+            reward,
+            done: (hit && self.reward.end_on_gong) || self.step >= self.reward.max_steps,
+            step: self.step,
+        }
+    }
+
+    fn gong_count(&self) -> usize {
+        self.world.read_storage::<::component::Gong>().join().count()
+    }
+
+    /// For each ball, agents then bots, whether it touches a gong
+    fn touching_gong(&self) -> Vec<bool> {
+        let contactors = self.world.read_storage::<::component::Contactor>();
+        let gongs = self.world.read_storage::<::component::Gong>();
+        self.agents.iter()
+            .chain(self.bots.iter())
+            .map(|entity| {
+                contactors.get(*entity)
+                    .map(|contactor| contactor.iter().any(|e| gongs.get(*e).is_some()))
+                    .unwrap_or(false)
+            })
+            .collect()
+    }
+
+    /// For each gong, whether a ball started to touch it during the last step
+    fn gong_hits(&self, started: &[bool]) -> Vec<bool> {
+        let contactors = self.world.read_storage::<::component::Contactor>();
+        let gongs = self.world.read_storage::<::component::Gong>();
+        let entities = self.world.entities();
+        (&*entities, &gongs).join()
+            .map(|(gong, _)| {
+                self.agents.iter()
+                    .chain(self.bots.iter())
+                    .zip(started.iter())
+                    .filter(|&(_, &started)| started)
+                    .any(|(entity, _)| {
+                        contactors.get(*entity)
+                            .map(|contactor| contactor.contains(&gong))
+                            .unwrap_or(false)
+                    })
+            })
+            .collect()
+    }
+
+    fn observe(&self, hits: Vec<bool>) -> Observation {
+        let airjumps = self.world.read_storage::<::component::Airjump>();
+        let gongs = self.world.read_storage::<::component::Gong>();
+        let bodies = self.world.read_storage::<::component::RigidBody>();
+        let physic_world = self.world.read_resource::<::resource::PhysicWorld>();
+
+        let balls = self.agents.iter()
+            .chain(self.bots.iter())
+            .filter_map(|entity| {
+                let body = match bodies.get(*entity)?.get(&physic_world) {
+                    Ok(body) => body,
+                    Err(error) => {
+                        ::error::log(error);
+                        return None;
+                    }
+                };
+                let airjump = airjumps.get(*entity).cloned().unwrap_or_default();
+                Some(BallObservation {
+                    body: BodyState::from_body(body),
+                    airjump: airjump.available(),
+                    airjumps: airjump.charges,
+                })
+            })
+            .collect();
+
+        let gongs = (&gongs, &bodies).join()
+            .filter_map(|(_, body)| body.get(&physic_world).ok())
+            .zip(hits)
+            .map(|(body, hit)| GongObservation {
+                body: BodyState::from_body(body),
+                hit,
+            })
+            .collect();
+
+        Observation { balls, gongs }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Answer requests read from `reader` until it is closed or a close request is received
+pub fn serve<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::Result<()> {
+    let mut env = Env::new();
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Reset { agents, bots, reward, rules }) => {
+                env.reset(agents.unwrap_or(2), bots, reward.unwrap_or_default(), rules.unwrap_or_default())
+            }
+            Ok(Request::Step { actions }) => env.step(&actions),
+            Ok(Request::Close) => return Ok(()),
+            Err(error) => {
+                serde_json::to_writer(&mut writer, &ErrorResponse { error: error.to_string() })?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+                continue;
+            }
+        };
+        serde_json::to_writer(&mut writer, &response)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+pub fn run() {
+    let result = match ::arg_value("--gym-port").and_then(|port| port.parse::<u16>().ok()) {
+        Some(port) => {
+            TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+                let (stream, _) = listener.accept()?;
+                serve(BufReader::new(stream.try_clone()?), stream)
+            })
+        }
+        None => {
+            let stdin = io::stdin();
+            let stdout = io::stdout();
+            let result = serve(stdin.lock(), stdout.lock());
+            result
+        }
+    };
+    if let Err(error) = result {
+        ::error::log(error);
+    }
+}
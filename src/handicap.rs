@@ -0,0 +1,75 @@
+//! Handicaps chosen by each player in the lobby.
+//!
+//! The `Handicap` of a slot is given to its balls by `entity::create_ball`: the size reshapes the
+//! body, `ControlSystem` applies the jump velocity and extra airjumps and `Play::gamepad` bends the
+//! aim toward the nearest gong.
+
+/// Choices of factor of the ball radius
+pub const SIZES: [f32; 4] = [0.8, 1.0, 1.25, 1.5];
+/// Choices of factor of the jump velocity
+pub const JUMPS: [f32; 4] = [0.8, 1.0, 1.2, 1.4];
+pub const MAX_EXTRA_AIRJUMPS: u32 = 3;
+/// Choices of aim assist, 0 for none
+pub const AIM_ASSISTS: [f32; 3] = [0.0, 0.25, 0.5];
+/// Angle in radian around the aim in which a gong attracts it
+pub const ASSIST_CONE: f32 = ::std::f32::consts::PI / 4.0;
+
+/// Turn the aim angle of a ball at `position` toward the nearest target inside `ASSIST_CONE`
+pub fn assist_aim(angle: f32, position: ::na::Vector2<f32>, targets: &[::na::Vector2<f32>], assist: f32) -> f32 {
+    let target = targets.iter()
+        .min_by(|a, b| (*a - position).norm().partial_cmp(&(*b - position).norm()).unwrap_or(::std::cmp::Ordering::Equal));
+    let target = match target {
+        Some(target) => target,
+        None => return angle,
+    };
+
+    let delta = target - position;
+    // Turn by the shortest way
+    let turn = ::wrap_angle(delta[1].atan2(delta[0]) - angle);
+    if turn.abs() > ASSIST_CONE {
+        angle
+    } else {
+        angle + turn * assist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::na::Vector2;
+    use std::f32::consts::PI;
+
+    #[test]
+    fn no_target() {
+        assert_eq!(assist_aim(0.5, Vector2::new(0.0, 0.0), &[], 1.0), 0.5);
+    }
+
+    #[test]
+    fn turn_toward_target_in_cone() {
+        // Target straight up, aim a bit to the right of it
+        let targets = [Vector2::new(0.0, 10.0)];
+        let angle = assist_aim(PI / 2.0 - 0.4, Vector2::new(0.0, 0.0), &targets, 0.5);
+        assert!((angle - (PI / 2.0 - 0.2)).abs() < 1e-5, "angle {}", angle);
+    }
+
+    #[test]
+    fn turn_across_the_wrap() {
+        // Target on the left, aim just below it on the other side of π
+        let targets = [Vector2::new(-10.0, 0.0)];
+        let angle = assist_aim(-PI + 0.2, Vector2::new(0.0, 0.0), &targets, 1.0);
+        assert!((::wrap_angle(angle) - PI).abs() < 1e-5 || (::wrap_angle(angle) + PI).abs() < 1e-5, "angle {}", angle);
+    }
+
+    #[test]
+    fn ignore_target_out_of_cone() {
+        let targets = [Vector2::new(0.0, 10.0)];
+        assert_eq!(assist_aim(0.0, Vector2::new(0.0, 0.0), &targets, 1.0), 0.0);
+    }
+
+    #[test]
+    fn aim_at_nearest_target() {
+        let targets = [Vector2::new(10.0, 10.0), Vector2::new(0.0, 2.0)];
+        let angle = assist_aim(PI / 2.0 - 0.1, Vector2::new(0.0, 0.0), &targets, 1.0);
+        assert!((angle - PI / 2.0).abs() < 1e-5, "angle {}", angle);
+    }
+}
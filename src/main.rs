@@ -19,6 +19,10 @@ extern crate enum_iterator_derive;
 #[macro_use]
 extern crate specs_derive;
 extern crate nalgebra as na;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
 
 mod graphics;
 mod resource;
@@ -27,33 +31,193 @@ mod entity;
 mod system;
 mod state;
 mod retained_storage;
-
-use specs::Join;
+mod error;
+mod snapshot;
+mod gym;
+mod net;
+mod server;
+mod preferences;
+mod binding;
+mod rules;
+mod mode;
+mod power_up;
+mod handicap;
+mod combat;
 
 #[derive(EnumIterator, Eq, PartialEq, Hash, Clone, Copy)]
 pub enum Image {
-    Start,
+    Lobby,
+    Ready,
+    NextRound,
+    RoundOver,
     Wait,
-    NewController,
-    NewControllerSkip,
-    NewController1,
-    NewController2,
+    Disconnected,
+    Pause,
+    Settings,
+    Rules,
+    JumpFixed,
+    JumpAnalog,
+    JumpCharged,
+    VelocityReset,
+    VelocityAdd,
+    ModeGong,
+    ModeHill,
+    ModeTag,
+    ModeSurvival,
+    ModeRace,
+    Winner,
+    Hill,
+    It,
+    Digit0,
+    Digit1,
+    Digit2,
+    Digit3,
+    Digit4,
+    Digit5,
+    Digit6,
+    Digit7,
+    Digit8,
+    Digit9,
+    DigitDot,
+    PowerAirjump,
+    PowerHeavy,
+    PowerBouncy,
+    PowerShrink,
+    PowerGravity,
+    Platform,
+    OneWay,
+    Bumper,
+    Spikes,
+    Wind,
+    GongRules,
+    MountSpring,
+    MountPendulum,
+    MountRail,
+    MountMoving,
+    ImpactAny,
+    ImpactMedium,
+    ImpactHard,
+    SuddenDeath,
+    Handicap,
+    Stun,
+    Regen1,
+    Regen2,
+    Regen4,
+    RestoreAny,
+    RestoreGround,
+    On,
+    Off,
+    Snap8,
+    Snap16,
+    Rebind,
+    BindAim1,
+    BindJump1,
+    BindAim2,
+    BindJump2,
+    BindConfirm,
+    BindBack,
+    BindOption,
+    BindAddBot,
+    BindRemoveBot,
+    BindStart,
+    BindUp,
+    BindDown,
+    BindLeft,
+    BindRight,
     Wallpaper,
     Ball,
     Gong,
+    BotEasy,
+    BotNormal,
+    BotHard,
 }
 impl Image {
     pub fn data(&self) -> &[u8] {
         match self {
-            Image::Start => include_bytes!("../assets/Start.png"),
+            Image::Lobby => include_bytes!("../assets/Lobby.png"),
+            Image::Ready => include_bytes!("../assets/Ready.png"),
+            Image::NextRound => include_bytes!("../assets/NextRound.png"),
+            Image::RoundOver => include_bytes!("../assets/RoundOver.png"),
             Image::Wait => include_bytes!("../assets/Wait.png"),
-            Image::NewController => include_bytes!("../assets/NewController.png"),
-            Image::NewControllerSkip => include_bytes!("../assets/NewControllerSkip.png"),
-            Image::NewController1 => include_bytes!("../assets/NewController1.png"),
-            Image::NewController2 => include_bytes!("../assets/NewController2.png"),
+            Image::Disconnected => include_bytes!("../assets/Disconnected.png"),
+            Image::Pause => include_bytes!("../assets/Pause.png"),
+            Image::Settings => include_bytes!("../assets/Settings.png"),
+            Image::Rules => include_bytes!("../assets/Rules.png"),
+            Image::JumpFixed => include_bytes!("../assets/JumpFixed.png"),
+            Image::JumpAnalog => include_bytes!("../assets/JumpAnalog.png"),
+            Image::JumpCharged => include_bytes!("../assets/JumpCharged.png"),
+            Image::VelocityReset => include_bytes!("../assets/VelocityReset.png"),
+            Image::VelocityAdd => include_bytes!("../assets/VelocityAdd.png"),
+            Image::ModeGong => include_bytes!("../assets/ModeGong.png"),
+            Image::ModeHill => include_bytes!("../assets/ModeHill.png"),
+            Image::ModeTag => include_bytes!("../assets/ModeTag.png"),
+            Image::ModeSurvival => include_bytes!("../assets/ModeSurvival.png"),
+            Image::ModeRace => include_bytes!("../assets/ModeRace.png"),
+            Image::Winner => include_bytes!("../assets/Winner.png"),
+            Image::Hill => include_bytes!("../assets/Hill.png"),
+            Image::It => include_bytes!("../assets/It.png"),
+            Image::Digit0 => include_bytes!("../assets/Digit0.png"),
+            Image::Digit1 => include_bytes!("../assets/Digit1.png"),
+            Image::Digit2 => include_bytes!("../assets/Digit2.png"),
+            Image::Digit3 => include_bytes!("../assets/Digit3.png"),
+            Image::Digit4 => include_bytes!("../assets/Digit4.png"),
+            Image::Digit5 => include_bytes!("../assets/Digit5.png"),
+            Image::Digit6 => include_bytes!("../assets/Digit6.png"),
+            Image::Digit7 => include_bytes!("../assets/Digit7.png"),
+            Image::Digit8 => include_bytes!("../assets/Digit8.png"),
+            Image::Digit9 => include_bytes!("../assets/Digit9.png"),
+            Image::DigitDot => include_bytes!("../assets/DigitDot.png"),
+            Image::PowerAirjump => include_bytes!("../assets/PowerAirjump.png"),
+            Image::PowerHeavy => include_bytes!("../assets/PowerHeavy.png"),
+            Image::PowerBouncy => include_bytes!("../assets/PowerBouncy.png"),
+            Image::PowerShrink => include_bytes!("../assets/PowerShrink.png"),
+            Image::PowerGravity => include_bytes!("../assets/PowerGravity.png"),
+            Image::Platform => include_bytes!("../assets/Platform.png"),
+            Image::OneWay => include_bytes!("../assets/OneWay.png"),
+            Image::Bumper => include_bytes!("../assets/Bumper.png"),
+            Image::Spikes => include_bytes!("../assets/Spikes.png"),
+            Image::Wind => include_bytes!("../assets/Wind.png"),
+            Image::GongRules => include_bytes!("../assets/GongRules.png"),
+            Image::MountSpring => include_bytes!("../assets/MountSpring.png"),
+            Image::MountPendulum => include_bytes!("../assets/MountPendulum.png"),
+            Image::MountRail => include_bytes!("../assets/MountRail.png"),
+            Image::MountMoving => include_bytes!("../assets/MountMoving.png"),
+            Image::ImpactAny => include_bytes!("../assets/ImpactAny.png"),
+            Image::ImpactMedium => include_bytes!("../assets/ImpactMedium.png"),
+            Image::ImpactHard => include_bytes!("../assets/ImpactHard.png"),
+            Image::SuddenDeath => include_bytes!("../assets/SuddenDeath.png"),
+            Image::Handicap => include_bytes!("../assets/Handicap.png"),
+            Image::Stun => include_bytes!("../assets/Stun.png"),
+            Image::Regen1 => include_bytes!("../assets/Regen1.png"),
+            Image::Regen2 => include_bytes!("../assets/Regen2.png"),
+            Image::Regen4 => include_bytes!("../assets/Regen4.png"),
+            Image::RestoreAny => include_bytes!("../assets/RestoreAny.png"),
+            Image::RestoreGround => include_bytes!("../assets/RestoreGround.png"),
+            Image::On => include_bytes!("../assets/On.png"),
+            Image::Off => include_bytes!("../assets/Off.png"),
+            Image::Snap8 => include_bytes!("../assets/Snap8.png"),
+            Image::Snap16 => include_bytes!("../assets/Snap16.png"),
+            Image::Rebind => include_bytes!("../assets/Rebind.png"),
+            Image::BindAim1 => include_bytes!("../assets/BindAim1.png"),
+            Image::BindJump1 => include_bytes!("../assets/BindJump1.png"),
+            Image::BindAim2 => include_bytes!("../assets/BindAim2.png"),
+            Image::BindJump2 => include_bytes!("../assets/BindJump2.png"),
+            Image::BindConfirm => include_bytes!("../assets/BindConfirm.png"),
+            Image::BindBack => include_bytes!("../assets/BindBack.png"),
+            Image::BindOption => include_bytes!("../assets/BindOption.png"),
+            Image::BindAddBot => include_bytes!("../assets/BindAddBot.png"),
+            Image::BindRemoveBot => include_bytes!("../assets/BindRemoveBot.png"),
+            Image::BindStart => include_bytes!("../assets/BindStart.png"),
+            Image::BindUp => include_bytes!("../assets/BindUp.png"),
+            Image::BindDown => include_bytes!("../assets/BindDown.png"),
+            Image::BindLeft => include_bytes!("../assets/BindLeft.png"),
+            Image::BindRight => include_bytes!("../assets/BindRight.png"),
             Image::Wallpaper => include_bytes!("../assets/Wallpaper.png"),
             Image::Ball => include_bytes!("../assets/Ball.png"),
             Image::Gong => include_bytes!("../assets/Gong.png"),
+            Image::BotEasy => include_bytes!("../assets/BotEasy.png"),
+            Image::BotNormal => include_bytes!("../assets/BotNormal.png"),
+            Image::BotHard => include_bytes!("../assets/BotHard.png"),
         }
     }
 }
@@ -75,14 +239,81 @@ pub fn safe_maintain(world: &mut specs::World) {
     for handle in &retained {
         bodies_map.remove(handle);
     }
+
+    if cfg!(debug_assertions) {
+        for error in bodies_map.validate(&physic_world) {
+            error::log(error);
+        }
+    }
 }
 
-fn main() {
-    ::std::env::set_var("WINIT_UNIX_BACKEND", "x11");
-    let mut events_loop = winit::EventsLoop::new();
-    let mut graphics = graphics::Graphics::new(&events_loop);
-    let mut gilrs = gilrs::Gilrs::new().unwrap();
+/// Duration of a simulation step, every step lasts the same so that timers and records don't
+/// depend on the frame rate
+pub const FIXED_STEP: f32 = 1.0 / 60.0;
+/// Most steps simulated in one frame, a game frozen for longer slows down instead of catching up
+pub const MAX_STEPS_PER_FRAME: u32 = 4;
+
+/// Same angle in radian within (-π, π], used to turn by the shortest way
+pub fn wrap_angle(angle: f32) -> f32 {
+    let turn = 2.0 * ::std::f32::consts::PI;
+    let angle = angle % turn;
+    if angle > ::std::f32::consts::PI {
+        angle - turn
+    } else if angle <= -::std::f32::consts::PI {
+        angle + turn
+    } else {
+        angle
+    }
+}
+
+/// Value following `name` in command line arguments
+pub fn arg_value(name: &str) -> Option<String> {
+    ::std::env::args().skip_while(|arg| arg != name).nth(1)
+}
+
+/// Poll window events, return pressed keys or `None` if the window is closed
+pub fn poll_window(events_loop: &mut winit::EventsLoop) -> Option<Vec<winit::VirtualKeyCode>> {
+    let mut done = false;
+    let mut pressed_keys = vec![];
+    events_loop.poll_events(|ev| match ev {
+        winit::Event::WindowEvent {
+            event: winit::WindowEvent::Closed,
+            ..
+        } => done = true,
+        winit::Event::WindowEvent {
+            event: winit::WindowEvent::KeyboardInput {
+                input: winit::KeyboardInput {
+                    state: winit::ElementState::Pressed,
+                    virtual_keycode: Some(key),
+                    ..
+                },
+                ..
+            },
+            ..
+        } => pressed_keys.push(key),
+        _ => (),
+    });
+    if done {
+        None
+    } else {
+        Some(pressed_keys)
+    }
+}
 
+/// Sleep until the end of the frame started at `last_frame_instant` and start the next one
+pub fn wait_frame(last_frame_instant: &mut std::time::Instant) {
+    let elapsed = last_frame_instant.elapsed();
+    let frame_duration = {
+        std::time::Duration::new(0, (1_000_000_000.0 / 60 as f32) as u32)
+    };
+    if let Some(to_sleep) = frame_duration.checked_sub(elapsed) {
+        std::thread::sleep(to_sleep);
+    }
+    *last_frame_instant = std::time::Instant::now();
+}
+
+/// Create the world with its resources, the gong, the ground and the walls
+pub fn create_world() -> specs::World {
     let mut physic_world = ::resource::PhysicWorld::new();
     physic_world.set_gravity(nphysics2d::math::Vector::new(0.0, entity::GRAVITY));
 
@@ -91,98 +322,239 @@ fn main() {
     world.register::<::component::Contactor>();
     world.register::<::component::Airjump>();
     world.register::<::component::AirjumpRestorer>();
+    world.register::<::component::Ground>();
+    world.register::<::component::It>();
+    world.register::<::component::Checkpoint>();
+    world.register::<::component::Pickup>();
+    world.register::<::component::PowerUps>();
+    world.register::<::component::Handicap>();
+    world.register::<::component::Hazard>();
+    world.register::<::component::Path>();
+    world.register::<::component::Wind>();
+    world.register::<::component::Spikes>();
+    world.register::<::component::OneWay>();
+    world.register::<::component::Respawn>();
+    world.register::<::component::Invulnerable>();
+    world.register::<::component::Stun>();
+    world.register::<::component::Impacts>();
+    world.register::<::component::Joint>();
     world.register::<::component::Control>();
     world.register::<::component::Image>();
+    world.register::<::component::Gong>();
+    world.register::<::component::Input>();
+    world.register::<::component::Bot>();
+    world.register::<::component::Player>();
+    world.register::<::component::Tint>();
+    world.register::<::component::AimFilter>();
+    world.register::<::component::JumpCharge>();
     world.add_resource(::resource::UpdateTime(0.0));
     world.add_resource(::resource::DrawImage(None));
+    world.add_resource(::resource::Sprites::default());
+    world.add_resource(::resource::Slots::default());
+    world.add_resource(::preferences::Preferences::default());
+    world.add_resource(::rules::Rules::default());
+    world.add_resource(::mode::RoundState::default());
+    world.add_resource(::mode::RoundTimer::default());
+    world.add_resource(::mode::RaceRecords::default());
+    world.add_resource(::resource::GamepadUuids::default());
     world.add_resource(::resource::BodiesMap::new());
     world.add_resource(physic_world);
-    let mut update_dispatcher = specs::DispatcherBuilder::new()
-        .with(::system::PhysicSystem, "physic", &[])
-        .with(::system::AirjumpSystem, "airjump", &["physic"])
-        .build();
 
-    entity::create_gong(&mut world);
-    entity::create_ground(&mut world);
+    entity::create_gongs(&::rules::GongRules::default(), &mut world);
+    if let Err(error) = entity::create_ground(&mut world) {
+        error::log(error);
+    }
     entity::create_walls(&mut world);
+    entity::create_checkpoints(&mut world);
+
+    world
+}
+
+pub fn create_update_dispatcher<'a, 'b>() -> specs::Dispatcher<'a, 'b> {
+    specs::DispatcherBuilder::new()
+        .with(::system::BotSystem, "bot", &[])
+        .with(::system::ControlSystem, "control", &["bot"])
+        .with(::system::PlatformSystem, "platform", &[])
+        .with(::system::PhysicSystem, "physic", &["control", "platform"])
+        .with(::system::WindSystem, "wind", &["physic"])
+        .with(::system::SpikeSystem, "spike", &["physic"])
+        .with(::system::RespawnSystem, "respawn", &["spike"])
+        .with(::system::OneWaySystem, "one_way", &["physic"])
+        .with(::system::AirjumpSystem, "airjump", &["physic"])
+        .with(::system::ModeSystem, "mode", &["physic"])
+        .with(::system::CombatSystem, "combat", &["physic"])
+        .with(::system::TimerSystem, "timer", &[])
+        .with(::system::PowerUpSystem, "power_up", &["physic"])
+        .build()
+}
+
+fn main() {
+    if ::std::env::args().any(|arg| arg == "--gym") {
+        gym::run();
+        return;
+    }
+    if let Some(peer) = arg_value("--net-peer") {
+        net::run(&peer);
+        return;
+    }
+    if let Some(address) = arg_value("--server") {
+        server::run_server(&address);
+        return;
+    }
+    if let Some(server) = arg_value("--connect") {
+        server::run_client(&server, false);
+        return;
+    }
+    if let Some(server) = arg_value("--spectate") {
+        server::run_client(&server, true);
+        return;
+    }
+
+    ::std::env::set_var("WINIT_UNIX_BACKEND", "x11");
+    let preferences = preferences::Preferences::load();
+    let mut events_loop = winit::EventsLoop::new();
+    let mut graphics = graphics::Graphics::new(&events_loop, preferences.fullscreen);
+    let mut gilrs = gilrs::Gilrs::new().unwrap();
+
+    let mut world = create_world();
+    world.add_resource(preferences.rules.clone());
+    world.add_resource(preferences);
+    for (id, gamepad) in gilrs.gamepads() {
+        world.write_resource::<::resource::GamepadUuids>().insert(id, *gamepad.uuid().as_bytes());
+    }
+    let mut update_dispatcher = create_update_dispatcher();
+
+    let mut loaded = false;
+    let mut args = ::std::env::args().skip(1).peekable();
+    while let Some(arg) = args.next() {
+        if arg == "--load" {
+            let path = args.next().unwrap_or(snapshot::SNAPSHOT_PATH.into());
+            match snapshot::Snapshot::load(&path) {
+                Ok(snapshot) => {
+                    snapshot.restore(&mut world);
+                    loaded = true;
+                }
+                Err(error) => error::log(error),
+            }
+        } else if arg == "--bot" {
+            let difficulty = args.peek()
+                .and_then(|name| ::component::Difficulty::from_name(name));
+            if difficulty.is_some() {
+                args.next();
+            }
+            let default_difficulty = world.read_resource::<preferences::Preferences>().bot_difficulty;
+            let player = ::resource::SlotPlayer::Bot(difficulty.unwrap_or(default_difficulty));
+            if let Some(slot) = world.write_resource::<::resource::Slots>().join(player, false) {
+                slot.ready = true;
+            }
+        }
+    }
 
     let mut last_frame_instant = std::time::Instant::now();
     let mut last_update_instant = std::time::Instant::now();
+    // Time elapsed but not simulated yet
+    let mut update_accumulator = 0.0;
+
+    let mut streamer = arg_value("--stream").and_then(|address| {
+        server::Streamer::bind(&address)
+            .map_err(error::log)
+            .ok()
+    });
 
-    let mut state = Box::new(state::Play) as Box<state::GameState>;
+    // A loaded game skips the lobby
+    let initial_state = if loaded {
+        Box::new(state::Play) as Box<state::GameState>
+    } else {
+        Box::new(state::Lobby) as Box<state::GameState>
+    };
+    let mut states = state::StateStack::new(initial_state, &mut world);
     loop {
         // Poll events
-        let mut done = false;
-        events_loop.poll_events(|ev| match ev {
-            winit::Event::WindowEvent {
-                event: winit::WindowEvent::Closed,
-                ..
-            } |
-            winit::Event::WindowEvent {
-                event: winit::WindowEvent::KeyboardInput {
-                    input: winit::KeyboardInput {
-                        virtual_keycode: Some(winit::VirtualKeyCode::Escape),
-                        ..
-                    },
-                    ..
-                },
-                ..
-            } => done = true,
-            _ => (),
-        });
-        if done {
-            return;
+        let pressed_keys = match poll_window(&mut events_loop) {
+            Some(pressed_keys) => pressed_keys,
+            None => return,
+        };
+        for key in pressed_keys {
+            match key {
+                winit::VirtualKeyCode::F5 => {
+                    if let Err(error) = snapshot::Snapshot::take(&world).save(snapshot::SNAPSHOT_PATH) {
+                        error::log(error);
+                    }
+                }
+                winit::VirtualKeyCode::F9 => {
+                    match snapshot::Snapshot::load(snapshot::SNAPSHOT_PATH) {
+                        Ok(snapshot) => snapshot.restore(&mut world),
+                        Err(error) => error::log(error),
+                    }
+                }
+                _ => states.key(key, &mut world),
+            }
         }
         while let Some(ev) = gilrs.next_event() {
             gilrs.update(&ev);
-            // Remove entities for disconnected controllers
-            // (this is ugly but we should have a system for that)
-            {
-                let controls = world.read_storage::<::component::Control>();
-                let entities = world.entities();
-                if ev.event == gilrs::ev::EventType::Disconnected {
-                    for (_, entity) in (&controls, &*entities).join().filter(|(c, _)| c.gamepad_id == ev.id) {
-                        entities.delete(entity).unwrap();
+            // Keep slots of disconnected controllers until they reconnect or time out
+            match ev.event {
+                gilrs::ev::EventType::Disconnected => {
+                    let gamepad = gilrs.gamepad(ev.id);
+                    world.write_resource::<::resource::Slots>()
+                        .disconnect(ev.id, *gamepad.uuid().as_bytes(), gamepad.name().into());
+                }
+                gilrs::ev::EventType::Connected => {
+                    let gamepad = gilrs.gamepad(ev.id);
+                    world.write_resource::<::resource::GamepadUuids>().insert(ev.id, *gamepad.uuid().as_bytes());
+                    let slot = world.write_resource::<::resource::Slots>()
+                        .reconnect(ev.id, *gamepad.uuid().as_bytes(), gamepad.name());
+                    if let Some(slot) = slot {
+                        entity::set_slot_gamepad(slot, ev.id, &world);
                     }
                 }
+                _ => (),
             }
-            state = state.event(ev, &mut world);
+            states.event(ev, &mut world);
         }
         for (id, gamepad) in gilrs.gamepads() {
-            state = state.gamepad(id, gamepad, &mut world);
+            states.gamepad(id, gamepad, &mut world);
         }
-        if state.quit() {
+        if states.quit() {
             return;
         }
+        let expired = world.write_resource::<::resource::Slots>().remove_expired();
+        entity::delete_slot_balls(&expired, &world);
 
-        // Update world
+        // Update world by fixed steps for the time elapsed
         let delta_time = last_update_instant.elapsed();
         last_update_instant = std::time::Instant::now();
-        if !state.paused(&world) {
-            world.write_resource::<::resource::UpdateTime>().0 = delta_time
+        if !states.paused(&world) {
+            let delta_time = delta_time
                 .as_secs()
                 .saturating_mul(1_000_000_000)
                 .saturating_add(delta_time.subsec_nanos() as u64)
                 as f32 / 1_000_000_000.0;
-            update_dispatcher.dispatch(&mut world.res);
+            update_accumulator = (update_accumulator + delta_time).min(MAX_STEPS_PER_FRAME as f32 * FIXED_STEP);
+            world.write_resource::<::resource::UpdateTime>().0 = FIXED_STEP;
+            while update_accumulator >= FIXED_STEP {
+                update_accumulator -= FIXED_STEP;
+                update_dispatcher.dispatch(&mut world.res);
+                safe_maintain(&mut world);
+            }
         } else {
             world.write_resource::<::resource::UpdateTime>().0 = 0.0;
             // pause_dispatcher.dispatch(&mut world.res);
         }
-        state = state.update(&mut world);
+        states.update(&mut world);
         safe_maintain(&mut world);
+        if let Some(ref mut streamer) = streamer {
+            if let Err(error) = streamer.tick(&world) {
+                error::log(error);
+            }
+        }
 
         // Draw world
+        states.render(&world);
+        graphics.set_fullscreen(world.read_resource::<preferences::Preferences>().fullscreen);
         graphics.render(&mut world);
 
         // Sleep
-        let elapsed = last_frame_instant.elapsed();
-        let frame_duration = {
-            std::time::Duration::new(0, (1_000_000_000.0 / 60 as f32) as u32)
-        };
-        if let Some(to_sleep) = frame_duration.checked_sub(elapsed) {
-            std::thread::sleep(to_sleep);
-        }
-        last_frame_instant = std::time::Instant::now();
+        wait_frame(&mut last_frame_instant);
     }
 }
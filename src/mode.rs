@@ -0,0 +1,455 @@
+//! Game modes: how a round progresses and who wins it.
+//!
+//! `ModeSystem` makes the round progress with the mode of the `Rules` resource and the game state
+//! asks `round_over` whether the round ended. `TimerSystem` counts the steps of the round in the
+//! `RoundTimer` resource and the state stack tells the state on top when the time runs out, which
+//! asks `time_up` who won.
+
+use specs;
+use specs::Join;
+use fnv::FnvHashMap;
+use rules::Mode;
+
+/// Center of the hill
+pub const HILL_POSITION: [f32; 2] = [0.0, 0.1];
+pub const HILL_RADIUS: f32 = 0.25;
+/// Time in second to spend in the hill to win
+pub const HILL_TIME: f32 = 10.0;
+/// Duration in second of a tag round
+pub const TAG_TIME: f32 = 30.0;
+/// Time in second before "it" can be passed again, so that it doesn't bounce back at once
+pub const TAG_COOLDOWN: f32 = 1.0;
+/// Time in second at the start of a survival round during which the ground is safe
+pub const SURVIVAL_GRACE: f32 = 2.0;
+/// Steps between two points of the path of a race
+pub const GHOST_STEPS: usize = 6;
+/// Points kept in the path of a race, the ghost of a longer race waits at the last one
+pub const GHOST_POINTS: usize = 6000;
+
+/// Race of a player
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Run {
+    /// Time in second at each checkpoint passed
+    pub splits: Vec<f32>,
+    /// Position of the first ball of the player every `GHOST_STEPS` steps, up to `GHOST_POINTS`
+    pub path: Vec<[f32; 2]>,
+    /// Time in second at the finish
+    pub finish: Option<f32>,
+}
+
+impl Run {
+    /// Position of the ghost of the race at the step of the round, interpolated along the path
+    pub fn ghost(&self, step: usize) -> Option<[f32; 2]> {
+        let last = self.path.len().checked_sub(1)?;
+        let index = step / GHOST_STEPS;
+        if index >= last {
+            return Some(self.path[last]);
+        }
+        let t = (step % GHOST_STEPS) as f32 / GHOST_STEPS as f32;
+        let (from, to) = (self.path[index], self.path[index + 1]);
+        Some([from[0] + (to[0] - from[0]) * t, from[1] + (to[1] - from[1]) * t])
+    }
+}
+
+/// Resource holding the best race of each slot id
+#[derive(Deref, DerefMut, Serialize, Deserialize, Clone, Default)]
+pub struct RaceRecords(pub FnvHashMap<usize, Run>);
+
+/// Resource holding the progress of the current round
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct RoundState {
+    /// Number of rounds started since the game started
+    pub round: usize,
+    /// Number of slots playing the round
+    pub slots: usize,
+    /// Time in second since the round started
+    pub time: f32,
+    /// Race of each slot id
+    pub runs: FnvHashMap<usize, Run>,
+    /// Hits on gongs by slot id
+    pub gong_hits: FnvHashMap<usize, u32>,
+    /// Time in second spent in the hill by slot id
+    pub hill: FnvHashMap<usize, f32>,
+    /// Time the last "it" was passed
+    pub tagged_at: f32,
+    /// Pickups spawned since the round started
+    pub pickups: usize,
+}
+
+/// Resource counting the simulation steps of the round, so that every peer runs out of time
+/// at the same step whatever its frame rate
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RoundTimer {
+    /// Steps since the round started
+    pub steps: usize,
+    /// Steps the round lasts, `None` without time limit
+    pub length: Option<usize>,
+    /// Set by `TimerSystem` at the step the time runs out, cleared once the state on top is told
+    pub time_up: bool,
+    /// Whether the time ran out on a tie, the first player taking the lead alone then wins
+    pub sudden_death: bool,
+}
+
+impl RoundTimer {
+    pub fn new(round_time: Option<f32>) -> Self {
+        RoundTimer {
+            length: round_time.map(|time| (time / ::FIXED_STEP).round() as usize),
+            ..RoundTimer::default()
+        }
+    }
+
+    /// Time in second left in the round
+    pub fn remaining(&self) -> Option<f32> {
+        self.length.map(|length| length.saturating_sub(self.steps) as f32 * ::FIXED_STEP)
+    }
+}
+
+/// Reset the progress for the round played by the balls
+pub fn start_round(balls: &[specs::Entity], world: &specs::World) {
+    let players = world.read_storage::<::component::Player>();
+    let mut slots = balls.iter()
+        .filter_map(|ball| players.get(*ball).map(|player| player.0))
+        .collect::<Vec<_>>();
+    slots.sort();
+    slots.dedup();
+
+    let mut round = world.write_resource::<RoundState>();
+    *round = RoundState {
+        round: round.round + 1,
+        slots: slots.len(),
+        ..RoundState::default()
+    };
+    *world.write_resource::<RoundTimer>() = RoundTimer::new(world.read_resource::<::rules::Rules>().round_time);
+
+    // Each round another ball starts as "it"
+    if world.read_resource::<::rules::Rules>().mode == Mode::Tag && !balls.is_empty() {
+        world.write_storage().insert(balls[round.round % balls.len()], ::component::It);
+    }
+}
+
+/// Slots winning the round if it is over, no slot wins a draw
+pub fn round_over(world: &specs::World) -> Option<Vec<usize>> {
+    let winners = mode_over(world);
+    if winners.is_none() && world.read_resource::<RoundTimer>().sudden_death {
+        let leaders = leaders(world);
+        if leaders.len() == 1 {
+            return Some(leaders);
+        }
+    }
+    winners
+}
+
+/// Slots winning the round by the objective of the mode
+fn mode_over(world: &specs::World) -> Option<Vec<usize>> {
+    let players = world.read_storage::<::component::Player>();
+    let round = world.read_resource::<RoundState>();
+
+    let mut winners = match world.read_resource::<::rules::Rules>().mode {
+        Mode::Gong => {
+            let hits = world.read_resource::<::rules::Rules>().gong.hits;
+            let ringers = round.gong_hits.iter()
+                .filter(|&(_, count)| *count >= hits)
+                .map(|(slot, _)| *slot)
+                .collect::<Vec<_>>();
+            if ringers.is_empty() {
+                return None;
+            }
+            ringers
+        }
+        Mode::KingOfTheHill => {
+            let kings = round.hill.iter()
+                .filter(|&(_, time)| *time >= HILL_TIME)
+                .map(|(slot, _)| *slot)
+                .collect::<Vec<_>>();
+            if kings.is_empty() {
+                return None;
+            }
+            kings
+        }
+        Mode::Tag => {
+            // With a round time the tag round lasts until the time is up
+            if round.time < TAG_TIME || world.read_resource::<::rules::Rules>().round_time.is_some() {
+                return None;
+            }
+            let its = world.read_storage::<::component::It>();
+            let losers = (&players, &its).join().map(|(player, _)| player.0).collect::<Vec<_>>();
+            players.join()
+                .map(|player| player.0)
+                .filter(|slot| !losers.contains(slot))
+                .collect::<Vec<_>>()
+        }
+        Mode::Survival => {
+            let mut alive = players.join().map(|player| player.0).collect::<Vec<_>>();
+            alive.sort();
+            alive.dedup();
+            if round.slots < 2 || alive.len() > 1 {
+                return None;
+            }
+            alive
+        }
+        Mode::Race => {
+            let finishers = round.runs.iter()
+                .filter(|&(_, run)| run.finish.is_some())
+                .map(|(slot, _)| *slot)
+                .collect::<Vec<_>>();
+            if finishers.is_empty() {
+                return None;
+            }
+            finishers
+        }
+    };
+    winners.sort();
+    winners.dedup();
+    Some(winners)
+}
+
+/// Slots winning the round when the time is up, `None` if the round goes on in sudden death
+pub fn time_up(world: &specs::World) -> Option<Vec<usize>> {
+    let rules = world.read_resource::<::rules::Rules>();
+    if rules.mode == Mode::Tag {
+        // Everybody but "it" wins as when the tag time is over
+        let players = world.read_storage::<::component::Player>();
+        let its = world.read_storage::<::component::It>();
+        let losers = (&players, &its).join().map(|(player, _)| player.0).collect::<Vec<_>>();
+        let mut winners = players.join()
+            .map(|player| player.0)
+            .filter(|slot| !losers.contains(slot))
+            .collect::<Vec<_>>();
+        winners.sort();
+        winners.dedup();
+        return Some(winners);
+    }
+
+    let leaders = leaders(world);
+    if leaders.len() < 2 {
+        Some(leaders)
+    } else if rules.sudden_death {
+        None
+    } else {
+        Some(vec![])
+    }
+}
+
+/// Slots ahead in the round: most gong hits, most time in the hill, still alive or most checkpoints
+/// passed, with the earliest split on a tie
+fn leaders(world: &specs::World) -> Vec<usize> {
+    let players = world.read_storage::<::component::Player>();
+    let slots = world.read_resource::<::resource::Slots>();
+    let round = world.read_resource::<RoundState>();
+
+    let mode = world.read_resource::<::rules::Rules>().mode;
+    let scores = slots.slots.iter()
+        .filter(|slot| !slot.queued)
+        .map(|slot| {
+            let score = match mode {
+                Mode::Gong => round.gong_hits.get(&slot.id).cloned().unwrap_or(0) as f32,
+                Mode::KingOfTheHill => round.hill.get(&slot.id).cloned().unwrap_or(0.0),
+                Mode::Tag | Mode::Survival => {
+                    if players.join().any(|player| player.0 == slot.id) { 1.0 } else { 0.0 }
+                }
+                Mode::Race => match round.runs.get(&slot.id).and_then(|run| run.splits.last().map(|split| (run, split))) {
+                    // Splits are shorter than the round so an earlier split ranks before another
+                    Some((run, split)) => run.splits.len() as f32 - split / (round.time + 1.0),
+                    None => 0.0,
+                },
+            };
+            (slot.id, score)
+        })
+        .collect::<Vec<_>>();
+
+    let best = scores.iter().fold(::std::f32::NEG_INFINITY, |best, &(_, score)| best.max(score));
+    scores.into_iter()
+        .filter(|&(_, score)| score == best)
+        .map(|(slot, _)| slot)
+        .collect()
+}
+
+/// Draw what the mode needs on top of the game: the hits on gongs, the hill and its progress, who
+/// is "it" or the checkpoints, times and ghosts of the race
+pub fn render(world: &specs::World) {
+    let bodies = world.read_storage::<::component::RigidBody>();
+    let physic_world = world.read_resource::<::resource::PhysicWorld>();
+    let slots = world.read_resource::<::resource::Slots>();
+    let round = world.read_resource::<RoundState>();
+    let timer = world.read_resource::<RoundTimer>();
+    let mut sprites = world.write_resource::<::resource::Sprites>();
+
+    // Time left at the top of the arena, or sudden death once it ran out
+    if timer.sudden_death {
+        sprites.push(::resource::Sprite {
+            image: ::Image::SuddenDeath,
+            position: [0.0, -0.96],
+            height: 0.03,
+            color: [1.0; 4],
+        });
+    } else if let Some(remaining) = timer.remaining() {
+        sprites.push_time(remaining, [0.0, -0.96], 0.04);
+    }
+
+    let rules = world.read_resource::<::rules::Rules>();
+    match rules.mode {
+        Mode::Gong => {
+            if rules.gong.hits < 2 {
+                return;
+            }
+            // Hits of each slot as small balls at the top of the arena
+            for (i, slot) in slots.slots.iter().enumerate() {
+                let hits = round.gong_hits.get(&slot.id).cloned().unwrap_or(0);
+                for j in 0..rules.gong.hits {
+                    sprites.push(::resource::Sprite {
+                        image: ::Image::Ball,
+                        position: [-0.6 + i as f32 * 0.4 + j as f32 * 0.03, -0.9],
+                        height: 0.025,
+                        color: if j < hits { ::resource::PLAYER_COLORS[slot.color] } else { [1.0, 1.0, 1.0, 0.3] },
+                    });
+                }
+            }
+        }
+        Mode::KingOfTheHill => {
+            sprites.push(::resource::Sprite {
+                image: ::Image::Hill,
+                position: HILL_POSITION,
+                height: HILL_RADIUS * 2.0,
+                color: [1.0, 0.85, 0.3, 1.0],
+            });
+            // Progress of each slot as small balls at the top of the arena
+            for (i, slot) in slots.slots.iter().enumerate() {
+                let time = round.hill.get(&slot.id).cloned().unwrap_or(0.0);
+                for j in 0..(time / HILL_TIME * 10.0) as usize {
+                    sprites.push(::resource::Sprite {
+                        image: ::Image::Ball,
+                        position: [-0.6 + i as f32 * 0.4 + j as f32 * 0.03, -0.9],
+                        height: 0.025,
+                        color: ::resource::PLAYER_COLORS[slot.color],
+                    });
+                }
+            }
+        }
+        Mode::Tag => {
+            let its = world.read_storage::<::component::It>();
+            for (_, body) in (&its, &bodies).join() {
+                match body.get(&physic_world) {
+                    Ok(body) => {
+                        let position = body.position().translation.vector;
+                        sprites.push(::resource::Sprite {
+                            image: ::Image::It,
+                            position: [position[0], position[1] - 0.1],
+                            height: 0.04,
+                            color: [1.0; 4],
+                        });
+                    }
+                    Err(error) => ::error::log(error),
+                }
+            }
+        }
+        Mode::Race => {
+            for (i, position) in ::entity::CHECKPOINTS.iter().enumerate() {
+                sprites.push(::resource::Sprite {
+                    image: ::Image::Hill,
+                    position: *position,
+                    height: ::entity::CHECKPOINT_RADIUS * 2.0,
+                    color: [0.5, 1.0, 0.5, 1.0],
+                });
+                sprites.push_number(i + 1, *position, 0.05);
+            }
+
+            let records = world.read_resource::<RaceRecords>();
+            for (i, slot) in slots.slots.iter().enumerate() {
+                let color = ::resource::PLAYER_COLORS[slot.color];
+                let x = -0.6 + i as f32 * 0.4;
+
+                // Ghost of the best race at the same step
+                if let Some(position) = records.get(&slot.id).and_then(|record| record.ghost(timer.steps)) {
+                    sprites.push(::resource::Sprite {
+                        image: ::Image::Ball,
+                        position,
+                        height: 0.1,
+                        color: [color[0], color[1], color[2], 0.35],
+                    });
+                }
+
+                // Last split and best time under a ball of the player color
+                sprites.push(::resource::Sprite {
+                    image: ::Image::Ball,
+                    position: [x - 0.12, -0.92],
+                    height: 0.03,
+                    color,
+                });
+                if let Some(split) = round.runs.get(&slot.id).and_then(|run| run.splits.last()) {
+                    sprites.push_time(*split, [x, -0.92], 0.04);
+                }
+                if let Some(best) = records.get(&slot.id).and_then(|record| record.finish) {
+                    sprites.push_time(best, [x, -0.86], 0.03);
+                }
+            }
+        }
+        Mode::Survival => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use specs::Builder;
+
+    fn world(mode: Mode, sudden_death: bool) -> specs::World {
+        let mut world = specs::World::new();
+        world.register::<::component::Player>();
+        world.register::<::component::It>();
+        let mut rules = ::rules::Rules::default();
+        rules.mode = mode;
+        rules.sudden_death = sudden_death;
+        world.add_resource(rules);
+        let mut slots = ::resource::Slots::default();
+        slots.join(::resource::SlotPlayer::Gamepad(0), false);
+        slots.join(::resource::SlotPlayer::Gamepad(1), false);
+        world.add_resource(slots);
+        world.add_resource(RoundState::default());
+        world
+    }
+
+    fn hit_gongs(world: &specs::World, hits: &[(usize, u32)]) {
+        world.write_resource::<RoundState>().gong_hits.extend(hits.iter().cloned());
+    }
+
+    #[test]
+    fn time_up_with_a_leader() {
+        let world = world(Mode::Gong, true);
+        hit_gongs(&world, &[(0, 3), (1, 2)]);
+        assert_eq!(time_up(&world), Some(vec![0]));
+    }
+
+    #[test]
+    fn time_up_on_a_tie() {
+        let world = world(Mode::Gong, true);
+        hit_gongs(&world, &[(0, 2), (1, 2)]);
+        assert_eq!(leaders(&world), vec![0, 1]);
+        assert_eq!(time_up(&world), None);
+    }
+
+    #[test]
+    fn time_up_on_a_tie_without_sudden_death() {
+        let world = world(Mode::Gong, false);
+        hit_gongs(&world, &[(0, 2), (1, 2)]);
+        assert_eq!(time_up(&world), Some(vec![]));
+    }
+
+    #[test]
+    fn time_up_in_tag() {
+        let mut world = world(Mode::Tag, true);
+        world.create_entity().with(::component::Player(0)).build();
+        world.create_entity().with(::component::Player(1)).with(::component::It).build();
+        assert_eq!(time_up(&world), Some(vec![0]));
+    }
+
+    #[test]
+    fn ghost_interpolates_the_path() {
+        let run = Run {
+            path: vec![[0.0, 0.0], [1.0, 2.0]],
+            ..Run::default()
+        };
+        assert_eq!(run.ghost(GHOST_STEPS / 2), Some([0.5, 1.0]));
+        assert_eq!(run.ghost(GHOST_STEPS * 10), Some([1.0, 2.0]));
+        assert_eq!(Run::default().ghost(0), None);
+    }
+}
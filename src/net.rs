@@ -0,0 +1,850 @@
+//! Peer to peer play over UDP with rollback.
+//!
+//! Each peer controls one ball with its first gamepad and sends its inputs to the other peer at
+//! every frame. Peers first exchange random nonces, the one with the lowest nonce controls the
+//! first ball. Frames whose inputs are all known are simulated in a confirmed world which never
+//! rolls back, so both peers compute it the same way and compare checksums of its frames: the
+//! session stops on a desync. The frames after it are predicted in the shown world with the last
+//! received remote input, which is put back in the confirmed state when a prediction was wrong.
+//!
+//! Start two peers on loopback with:
+//! ```sh
+//! airjump-multi --net-bind 127.0.0.1:7000 --net-peer 127.0.0.1:7001
+//! airjump-multi --net-bind 127.0.0.1:7001 --net-peer 127.0.0.1:7000
+//! ```
+//! `--net-delay <frames>` delays local inputs to reduce rollbacks, `--net-latency <ms>` and
+//! `--net-loss <ratio>` simulate a bad network on outgoing packets.
+
+use specs;
+use specs::Join;
+use serde_json;
+use fnv::FnvHasher;
+use std::collections::VecDeque;
+use std::hash::Hasher;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use snapshot::BodyState;
+
+/// Maximal number of frames simulated ahead of the last received remote input
+pub const MAX_PREDICTION: u32 = 30;
+/// Number of frames a local input is delayed by default
+pub const DEFAULT_DELAY: u32 = 2;
+
+const MAX_PACKET_SIZE: usize = 16384;
+const MAX_INPUTS_PER_PACKET: usize = 64;
+
+/// Delay and drop outgoing packets to test network conditions on loopback
+pub struct LinkConditioner {
+    pub latency: Duration,
+    /// Ratio of packets dropped
+    pub loss: f32,
+    seed: u32,
+    queue: VecDeque<(Instant, Vec<u8>)>,
+}
+
+impl LinkConditioner {
+    pub fn new(latency: Duration, loss: f32) -> Self {
+        LinkConditioner {
+            latency,
+            loss,
+            seed: 0x2545_f491,
+            queue: VecDeque::new(),
+        }
+    }
+
+    /// Random number in [0, 1]
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed as f64 / u32::max_value() as f64) as f32
+    }
+}
+
+/// Non blocking UDP socket sending JSON packets
+pub struct Transport {
+    socket: UdpSocket,
+    conditioner: Option<LinkConditioner>,
+}
+
+impl Transport {
+    pub fn bind(address: &str, conditioner: Option<LinkConditioner>) -> io::Result<Self> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_nonblocking(true)?;
+        Ok(Transport {
+            socket,
+            conditioner,
+        })
+    }
+
+    pub fn send<T: ::serde::Serialize>(&mut self, packet: &T, address: SocketAddr) -> io::Result<()> {
+        let data = serde_json::to_vec(packet)?;
+        match self.conditioner {
+            Some(ref mut conditioner) => {
+                if conditioner.random() >= conditioner.loss {
+                    let instant = Instant::now() + conditioner.latency;
+                    conditioner.queue.push_back((instant, data));
+                }
+                self.flush(address)
+            }
+            None => self.socket.send_to(&data, address).map(|_| ()),
+        }
+    }
+
+    /// Send packets delayed by the link conditioner
+    pub fn flush(&mut self, address: SocketAddr) -> io::Result<()> {
+        if let Some(ref mut conditioner) = self.conditioner {
+            let now = Instant::now();
+            while conditioner.queue.front().map(|&(instant, _)| instant <= now).unwrap_or(false) {
+                let (_, data) = conditioner.queue.pop_front().unwrap();
+                self.socket.send_to(&data, address)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Receive all pending packets, malformed ones are logged and dropped
+    pub fn receive<T: ::serde::de::DeserializeOwned>(&mut self) -> io::Result<Vec<(T, SocketAddr)>> {
+        let mut packets = vec![];
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((size, address)) => match serde_json::from_slice(&buffer[..size]) {
+                    Ok(packet) => packets.push((packet, address)),
+                    Err(error) => ::error::log(error),
+                },
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock => return Ok(packets),
+                // On some platforms an unreachable peer is reported on the next receive
+                Err(ref error) if error.kind() == io::ErrorKind::ConnectionReset => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Scale of quantized angles, (-π, π] maps to the range of `i16`
+const ANGLE_SCALE: f32 = ::std::i16::MAX as f32 / ::std::f32::consts::PI;
+
+/// Input quantized so that it is identical on both peers
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct NetInput {
+    /// Angle in π/32767 radian
+    pub angle: Option<i16>,
+    pub jump: bool,
+    /// Trigger pressure in 1/255
+    pub pressure: Option<u8>,
+}
+
+impl NetInput {
+    pub fn from_input(input: &::component::Input) -> Self {
+        NetInput {
+            angle: input.angle.map(|angle| (::wrap_angle(angle) * ANGLE_SCALE).round() as i16),
+            jump: input.jump,
+            pressure: input.pressure.map(|pressure| (pressure.max(0.0).min(1.0) * 255.0).round() as u8),
+        }
+    }
+
+    pub fn to_input(&self) -> ::component::Input {
+        ::component::Input {
+            angle: self.angle.map(|angle| angle as f32 / ANGLE_SCALE),
+            jump: self.jump,
+            pressure: self.pressure.map(|pressure| pressure as f32 / 255.0),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InputPacket {
+    /// Random number of the sender, the peer with the lowest one controls the first ball
+    pub nonce: u64,
+    /// Frame of the first input
+    pub start: u32,
+    pub inputs: Vec<NetInput>,
+    /// Number of inputs of the receiver already received by the sender
+    pub received: u32,
+    /// Last confirmed frame of the sender with the checksum of the state at its end
+    pub checksum: Option<(u32, u64)>,
+}
+
+/// Number of confirmed checksums kept to be compared with the ones of the peer
+const CHECKSUM_HISTORY: usize = 4 * MAX_PREDICTION as usize;
+
+/// Random number identifying this run of the game in the handshake
+fn new_nonce() -> u64 {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs() ^ (time.subsec_nanos() as u64) << 32)
+        .unwrap_or(0);
+    time ^ ::std::process::id() as u64
+}
+
+/// Components of a ball changed by the simulation
+#[derive(Serialize, Deserialize)]
+struct BallState {
+    body: BodyState,
+    airjump: ::component::Airjump,
+    jump_charge: Option<::component::JumpCharge>,
+    power_ups: ::component::PowerUps,
+    stun: Option<::component::Stun>,
+    invulnerable: Option<::component::Invulnerable>,
+    it: bool,
+    respawn: bool,
+    /// Only kept between the worlds of a session, new bodies report their contacts again
+    #[serde(skip)]
+    contacts: Vec<specs::Entity>,
+}
+
+/// Everything the simulation changes at the end of a frame, the rest of the world is created the
+/// same way on both peers. Snapshots saved to a file hold one too.
+#[derive(Serialize, Deserialize)]
+pub struct FrameState {
+    /// Ball of each player, `None` once eliminated
+    balls: Vec<Option<BallState>>,
+    gongs: Vec<BodyState>,
+    /// Moving platforms with the index of the point they go to
+    platforms: Vec<(BodyState, usize)>,
+    pickups: Vec<::component::Pickup>,
+    round: ::mode::RoundState,
+    timer: ::mode::RoundTimer,
+    records: ::mode::RaceRecords,
+}
+
+impl FrameState {
+    pub fn take(players: &[specs::Entity], world: &specs::World) -> Self {
+        let entities = world.entities();
+        let airjumps = world.read_storage::<::component::Airjump>();
+        let jump_charges = world.read_storage::<::component::JumpCharge>();
+        let power_ups = world.read_storage::<::component::PowerUps>();
+        let stuns = world.read_storage::<::component::Stun>();
+        let invulnerables = world.read_storage::<::component::Invulnerable>();
+        let its = world.read_storage::<::component::It>();
+        let respawns = world.read_storage::<::component::Respawn>();
+        let contactors = world.read_storage::<::component::Contactor>();
+        let gongs = world.read_storage::<::component::Gong>();
+        let paths = world.read_storage::<::component::Path>();
+        let pickups = world.read_storage::<::component::Pickup>();
+        let bodies = world.read_storage::<::component::RigidBody>();
+        let physic_world = world.read_resource::<::resource::PhysicWorld>();
+
+        let balls = players.iter()
+            .map(|&entity| {
+                if !entities.is_alive(entity) {
+                    return None;
+                }
+                let body = match bodies.get(entity)?.get(&physic_world) {
+                    Ok(body) => body,
+                    Err(error) => {
+                        ::error::log(error);
+                        return None;
+                    }
+                };
+                Some(BallState {
+                    body: BodyState::from_body(body),
+                    airjump: airjumps.get(entity).cloned().unwrap_or_default(),
+                    jump_charge: jump_charges.get(entity).cloned(),
+                    power_ups: power_ups.get(entity).cloned().unwrap_or_default(),
+                    stun: stuns.get(entity).cloned(),
+                    invulnerable: invulnerables.get(entity).cloned(),
+                    it: its.get(entity).is_some(),
+                    respawn: respawns.get(entity).is_some(),
+                    contacts: contactors.get(entity).map(|contactor| contactor.0.clone()).unwrap_or_default(),
+                })
+            })
+            .collect();
+
+        let gongs = (&gongs, &bodies).join()
+            .filter_map(|(_, body)| body.get(&physic_world).ok())
+            .map(BodyState::from_body)
+            .collect();
+
+        let platforms = (&paths, &bodies).join()
+            .filter_map(|(path, body)| body.get(&physic_world).ok().map(|body| (BodyState::from_body(body), path.next)))
+            .collect();
+
+        FrameState {
+            balls,
+            gongs,
+            platforms,
+            pickups: (&pickups).join().cloned().collect(),
+            round: world.read_resource::<::mode::RoundState>().clone(),
+            timer: world.read_resource::<::mode::RoundTimer>().clone(),
+            records: world.read_resource::<::mode::RaceRecords>().clone(),
+        }
+    }
+
+    /// Put the world in this state, its balls are the same entities as the ones of the world the
+    /// state was taken from
+    pub fn restore(&self, players: &[specs::Entity], world: &mut specs::World) {
+        // Pickups are recreated when they differ, the ones of the other world are other entities
+        let pickups_changed = {
+            let pickups = world.read_storage::<::component::Pickup>();
+            pickups.join().cloned().collect::<Vec<_>>() != self.pickups
+        };
+        if pickups_changed {
+            let pickups = world.read_storage::<::component::Pickup>();
+            let entities = world.entities();
+            for (entity, _) in (&*entities, &pickups).join() {
+                if let Err(error) = entities.delete(entity) {
+                    ::error::log(error);
+                }
+            }
+        }
+        {
+            let entities = world.entities();
+            let handicaps = world.read_storage::<::component::Handicap>();
+            let mut airjumps = world.write_storage::<::component::Airjump>();
+            let mut jump_charges = world.write_storage::<::component::JumpCharge>();
+            let mut power_ups = world.write_storage::<::component::PowerUps>();
+            let mut stuns = world.write_storage::<::component::Stun>();
+            let mut invulnerables = world.write_storage::<::component::Invulnerable>();
+            let mut its = world.write_storage::<::component::It>();
+            let mut respawns = world.write_storage::<::component::Respawn>();
+            let mut contactors = world.write_storage::<::component::Contactor>();
+            let mut images = world.write_storage::<::component::Image>();
+            let mut bodies = world.write_storage::<::component::RigidBody>();
+            let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
+            let mut bodies_map = world.write_resource::<::resource::BodiesMap>();
+
+            for (&entity, ball) in players.iter().zip(self.balls.iter()) {
+                if !entities.is_alive(entity) {
+                    continue;
+                }
+                let ball = match ball {
+                    Some(ball) => ball,
+                    None => {
+                        if let Err(error) = entities.delete(entity) {
+                            ::error::log(error);
+                        }
+                        continue;
+                    }
+                };
+
+                // The body of the ball depends on its power-ups
+                let handicap = handicaps.get(entity).cloned().unwrap_or_default();
+                let shape = ::power_up::ball_shape(&ball.power_ups, &handicap);
+                if power_ups.get(entity).map(|power_ups| ::power_up::ball_shape(power_ups, &handicap)) != Some(shape) {
+                    let result = ::entity::set_ball_shape(
+                        entity,
+                        shape,
+                        &mut bodies,
+                        &mut images,
+                        &mut contactors,
+                        &mut physic_world,
+                        &mut bodies_map,
+                    );
+                    if let Err(error) = result {
+                        ::error::log(error);
+                    }
+                }
+                if let Some(body) = bodies.get(entity) {
+                    match body.get_mut(&mut physic_world) {
+                        Ok(body) => ball.body.apply(body),
+                        Err(error) => ::error::log(error),
+                    }
+                }
+
+                airjumps.insert(entity, ball.airjump);
+                power_ups.insert(entity, ball.power_ups.clone());
+                // Entities of the other world, like its pickups, may not exist in this one
+                let contacts = ball.contacts.iter()
+                    .cloned()
+                    .filter(|&contact| entities.is_alive(contact))
+                    .collect();
+                contactors.insert(entity, ::component::Contactor(contacts));
+                restore_component(&mut jump_charges, entity, ball.jump_charge);
+                restore_component(&mut stuns, entity, ball.stun);
+                restore_component(&mut invulnerables, entity, ball.invulnerable);
+                if ball.it {
+                    its.insert(entity, ::component::It);
+                } else {
+                    its.remove(entity);
+                }
+                if ball.respawn {
+                    respawns.insert(entity, ::component::Respawn);
+                } else {
+                    respawns.remove(entity);
+                }
+            }
+
+            let gongs = world.read_storage::<::component::Gong>();
+            for ((_, body), state) in (&gongs, &bodies).join().zip(self.gongs.iter()) {
+                match body.get_mut(&mut physic_world) {
+                    Ok(body) => state.apply(body),
+                    Err(error) => ::error::log(error),
+                }
+            }
+
+            let mut paths = world.write_storage::<::component::Path>();
+            for ((path, body), &(ref state, next)) in (&mut paths, &bodies).join().zip(self.platforms.iter()) {
+                path.next = next;
+                match body.get_mut(&mut physic_world) {
+                    Ok(body) => state.apply(body),
+                    Err(error) => ::error::log(error),
+                }
+            }
+        }
+        ::safe_maintain(world);
+
+        if pickups_changed {
+            let entities = world.entities();
+            for pickup in &self.pickups {
+                ::entity::create_pickup(
+                    pickup.power_up,
+                    pickup.spot,
+                    &entities,
+                    &mut world.write_storage(),
+                    &mut world.write_storage(),
+                    &mut world.write_storage(),
+                    &mut world.write_resource(),
+                    &mut world.write_resource(),
+                );
+            }
+        }
+
+        *world.write_resource::<::mode::RoundState>() = self.round.clone();
+        *world.write_resource::<::mode::RoundTimer>() = self.timer.clone();
+        *world.write_resource::<::mode::RaceRecords>() = self.records.clone();
+    }
+
+    /// Hash of the state compared between peers, numbers are hashed bit for bit
+    fn checksum(&self) -> u64 {
+        fn hash_body(hasher: &mut FnvHasher, body: &BodyState) {
+            let values = [
+                body.position[0],
+                body.position[1],
+                body.angle,
+                body.linear_velocity[0],
+                body.linear_velocity[1],
+                body.angular_velocity,
+            ];
+            for value in values.iter() {
+                hasher.write_u32(value.to_bits());
+            }
+        }
+
+        let mut hasher = FnvHasher::default();
+        for ball in &self.balls {
+            match ball {
+                Some(ball) => {
+                    hasher.write_u8(1);
+                    hash_body(&mut hasher, &ball.body);
+                    hasher.write_u32(ball.airjump.charges);
+                    hasher.write_u32(ball.airjump.regen.to_bits());
+                    hasher.write_usize(ball.power_ups.len());
+                    hasher.write_u8(ball.it as u8);
+                }
+                None => hasher.write_u8(0),
+            }
+        }
+        for gong in &self.gongs {
+            hash_body(&mut hasher, gong);
+        }
+        for &(ref platform, next) in &self.platforms {
+            hash_body(&mut hasher, platform);
+            hasher.write_usize(next);
+        }
+        for pickup in &self.pickups {
+            hasher.write_usize(pickup.spot);
+        }
+        hasher.write_usize(self.timer.steps);
+        let mut gong_hits = self.round.gong_hits.iter().collect::<Vec<_>>();
+        gong_hits.sort();
+        for (slot, hits) in gong_hits {
+            hasher.write_usize(*slot);
+            hasher.write_u32(*hits);
+        }
+        hasher.finish()
+    }
+}
+
+fn restore_component<'a, T: specs::Component + Clone>(
+    storage: &mut specs::WriteStorage<'a, T>,
+    entity: specs::Entity,
+    component: Option<T>,
+) {
+    match component {
+        Some(component) => {
+            storage.insert(entity, component);
+        }
+        None => {
+            storage.remove(entity);
+        }
+    }
+}
+
+/// Simulate one frame of the world with the input of each player
+fn simulate<'a, 'b>(
+    dispatcher: &mut specs::Dispatcher<'a, 'b>,
+    players: &[specs::Entity; 2],
+    inputs: [NetInput; 2],
+    world: &mut specs::World,
+) {
+    {
+        let mut components = world.write_storage::<::component::Input>();
+        for (player, input) in players.iter().zip(inputs.iter()) {
+            if let Some(component) = components.get_mut(*player) {
+                *component = input.to_input();
+            }
+        }
+    }
+    world.write_resource::<::resource::UpdateTime>().0 = ::FIXED_STEP;
+    dispatcher.dispatch(&mut world.res);
+    ::safe_maintain(world);
+}
+
+/// Rollback session between two peers, each one controlling one ball
+///
+/// Frames are simulated in a confirmed world once the inputs of both players are known, it never
+/// rolls back so both peers simulate it the same way. The frames after it are predicted in the
+/// shown world with the last remote input.
+pub struct Session<'a, 'b> {
+    transport: Transport,
+    peer: SocketAddr,
+    dispatcher: specs::Dispatcher<'a, 'b>,
+    confirmed: specs::World,
+    /// Ball of each player, the same entities in the confirmed world and in the shown one
+    players: [specs::Entity; 2],
+    nonce: u64,
+    peer_nonce: Option<u64>,
+    /// Index of the local player in `players`, known once peers exchanged their nonces
+    local: Option<usize>,
+    delay: u32,
+    /// Next frame to show
+    frame: u32,
+    /// Next frame to simulate in the confirmed world
+    confirmed_frame: u32,
+    /// Known inputs of each peer indexed by frame
+    local_inputs: Vec<NetInput>,
+    remote_inputs: Vec<NetInput>,
+    /// Number of local inputs received by the peer
+    acked: u32,
+    /// Checksums of the last confirmed frames of each peer
+    checksums: VecDeque<(u32, u64)>,
+    peer_checksums: VecDeque<(u32, u64)>,
+    /// Next frame to simulate in the shown world
+    shown_frame: u32,
+    /// Inputs the last frames of the shown world were simulated with, up to `shown_frame`
+    shown_inputs: VecDeque<[NetInput; 2]>,
+}
+
+impl<'a, 'b> Session<'a, 'b> {
+    pub fn new(transport: Transport, peer: SocketAddr, delay: u32, world: &mut specs::World) -> Self {
+        let mut confirmed = ::create_world();
+        let players = [
+            ::entity::create_ball_entity(&mut confirmed),
+            ::entity::create_ball_entity(&mut confirmed),
+        ];
+        ::entity::spread_balls(&players, &confirmed);
+
+        // Both worlds are created the same way so their entities match
+        let shown = [
+            ::entity::create_ball_entity(world),
+            ::entity::create_ball_entity(world),
+        ];
+        debug_assert_eq!(players, shown);
+        FrameState::take(&players, &confirmed).restore(&players, world);
+
+        Session {
+            transport,
+            peer,
+            dispatcher: ::create_update_dispatcher(),
+            confirmed,
+            players,
+            nonce: new_nonce(),
+            peer_nonce: None,
+            local: None,
+            delay,
+            frame: 0,
+            confirmed_frame: 0,
+            local_inputs: vec![],
+            remote_inputs: vec![],
+            acked: 0,
+            checksums: VecDeque::new(),
+            peer_checksums: VecDeque::new(),
+            shown_frame: 0,
+            shown_inputs: VecDeque::new(),
+        }
+    }
+
+    pub fn connected(&self) -> bool {
+        self.local.is_some()
+    }
+
+    /// Record local input, apply received inputs and show the next frame if not too far ahead of
+    /// the peer
+    pub fn tick(&mut self, input: &::component::Input, world: &mut specs::World) -> Result<(), ::error::NetError> {
+        self.receive()?;
+
+        if self.connected() {
+            if self.local_inputs.len() as u32 <= self.frame + self.delay {
+                while (self.local_inputs.len() as u32) < self.frame + self.delay {
+                    self.local_inputs.push(NetInput::default());
+                }
+                self.local_inputs.push(NetInput::from_input(input));
+            }
+            if self.frame < self.remote_inputs.len() as u32 + MAX_PREDICTION {
+                self.frame += 1;
+            }
+            self.confirm()?;
+            self.predict(world);
+        }
+
+        self.send()
+    }
+
+    fn receive(&mut self) -> Result<(), ::error::NetError> {
+        for (packet, address) in self.transport.receive::<InputPacket>()? {
+            if address != self.peer {
+                continue;
+            }
+            // The peer with the lowest nonce controls the first ball
+            match self.peer_nonce {
+                None if packet.nonce == self.nonce => {
+                    self.nonce = new_nonce();
+                    continue;
+                }
+                None => {
+                    self.peer_nonce = Some(packet.nonce);
+                    self.local = Some(if self.nonce < packet.nonce { 0 } else { 1 });
+                }
+                // Packet of a previous run of the peer
+                Some(nonce) if nonce != packet.nonce => continue,
+                Some(_) => (),
+            }
+            self.acked = self.acked.max(packet.received);
+
+            // Packets start at the first input not acked, inputs after a gap are dropped
+            let received = self.remote_inputs.len() as u32;
+            if packet.start <= received {
+                let skip = (received - packet.start) as usize;
+                self.remote_inputs.extend(packet.inputs.into_iter().skip(skip));
+            }
+
+            if let Some(checksum) = packet.checksum {
+                if self.peer_checksums.back().map(|&(frame, _)| frame < checksum.0).unwrap_or(true) {
+                    self.peer_checksums.push_back(checksum);
+                }
+                while self.peer_checksums.len() > CHECKSUM_HISTORY {
+                    self.peer_checksums.pop_front();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn player_inputs(&self, frame: u32) -> [NetInput; 2] {
+        let local = self.local.unwrap_or(0);
+        let mut inputs = [NetInput::default(); 2];
+        inputs[local] = self.local_inputs.get(frame as usize).cloned().unwrap_or_default();
+        // Remote inputs not received yet are predicted as the last one
+        inputs[1 - local] = self.remote_inputs.get(frame as usize)
+            .or(self.remote_inputs.last())
+            .cloned()
+            .unwrap_or_default();
+        inputs
+    }
+
+    /// Simulate the frames whose inputs are all known in the confirmed world and compare their
+    /// checksums with the peer ones
+    fn confirm(&mut self) -> Result<(), ::error::NetError> {
+        let known = (self.local_inputs.len() as u32)
+            .min(self.remote_inputs.len() as u32)
+            .min(self.frame);
+        while self.confirmed_frame < known {
+            let inputs = self.player_inputs(self.confirmed_frame);
+            simulate(&mut self.dispatcher, &self.players, inputs, &mut self.confirmed);
+            self.confirmed_frame += 1;
+
+            let checksum = FrameState::take(&self.players, &self.confirmed).checksum();
+            self.checksums.push_back((self.confirmed_frame, checksum));
+            while self.checksums.len() > CHECKSUM_HISTORY {
+                self.checksums.pop_front();
+            }
+        }
+
+        while let Some(&(frame, checksum)) = self.peer_checksums.front() {
+            if frame > self.confirmed_frame {
+                break;
+            }
+            // Checksums older than the history can't be compared anymore
+            if let Some(&(_, own)) = self.checksums.iter().find(|&&(own_frame, _)| own_frame == frame) {
+                if own != checksum {
+                    return Err(::error::NetError::Desync { frame });
+                }
+            }
+            self.peer_checksums.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Simulate the shown world up to the current frame, it is put back in the confirmed state
+    /// only if it was simulated with inputs that turned out different
+    fn predict(&mut self, world: &mut specs::World) {
+        let first = self.shown_frame - self.shown_inputs.len() as u32;
+        let mispredicted = self.shown_inputs.iter()
+            .zip(first..)
+            .any(|(inputs, frame)| *inputs != self.player_inputs(frame));
+        if mispredicted {
+            FrameState::take(&self.players, &self.confirmed).restore(&self.players, world);
+            self.shown_frame = self.confirmed_frame;
+            self.shown_inputs.clear();
+        } else {
+            // Inputs of confirmed frames can't change anymore
+            let confirmed = (self.confirmed_frame.min(self.shown_frame) - first) as usize;
+            self.shown_inputs.drain(..confirmed);
+        }
+
+        while self.shown_frame < self.frame {
+            let inputs = self.player_inputs(self.shown_frame);
+            simulate(&mut self.dispatcher, &self.players, inputs, world);
+            self.shown_inputs.push_back(inputs);
+            self.shown_frame += 1;
+        }
+    }
+
+    fn send(&mut self) -> Result<(), ::error::NetError> {
+        let start = self.acked.min(self.local_inputs.len() as u32);
+        let packet = InputPacket {
+            nonce: self.nonce,
+            start,
+            inputs: self.local_inputs
+                .iter()
+                .skip(start as usize)
+                .take(MAX_INPUTS_PER_PACKET)
+                .cloned()
+                .collect(),
+            received: self.remote_inputs.len() as u32,
+            checksum: self.checksums.back().cloned(),
+        };
+        self.transport.send(&packet, self.peer)?;
+        self.transport.flush(self.peer)?;
+        Ok(())
+    }
+}
+
+pub fn resolve(address: &str) -> io::Result<SocketAddr> {
+    ToSocketAddrs::to_socket_addrs(address)?
+        .next()
+        .ok_or(io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", address)))
+}
+
+pub fn run(peer: &str) {
+    let bind = ::arg_value("--net-bind").unwrap_or("0.0.0.0:7000".into());
+    let delay = ::arg_value("--net-delay")
+        .and_then(|delay| delay.parse().ok())
+        .unwrap_or(DEFAULT_DELAY);
+    let latency = ::arg_value("--net-latency")
+        .and_then(|latency| latency.parse().ok())
+        .map(Duration::from_millis);
+    let loss = ::arg_value("--net-loss").and_then(|loss| loss.parse().ok());
+    let conditioner = if latency.is_some() || loss.is_some() {
+        Some(LinkConditioner::new(latency.unwrap_or(Duration::from_millis(0)), loss.unwrap_or(0.0)))
+    } else {
+        None
+    };
+
+    let peer = match resolve(peer) {
+        Ok(peer) => peer,
+        Err(error) => {
+            ::error::log(error);
+            return;
+        }
+    };
+    let transport = match Transport::bind(&bind, conditioner) {
+        Ok(transport) => transport,
+        Err(error) => {
+            ::error::log(error);
+            return;
+        }
+    };
+
+    ::std::env::set_var("WINIT_UNIX_BACKEND", "x11");
+    let preferences = ::preferences::Preferences::load();
+    let mut events_loop = ::winit::EventsLoop::new();
+    let mut graphics = ::graphics::Graphics::new(&events_loop, preferences.fullscreen);
+    let mut gilrs = ::gilrs::Gilrs::new().unwrap();
+
+    let mut world = ::create_world();
+    let mut session = Session::new(transport, peer, delay, &mut world);
+
+    let mut last_frame_instant = Instant::now();
+    loop {
+        match ::poll_window(&mut events_loop) {
+            Some(ref keys) if !keys.contains(&::winit::VirtualKeyCode::Escape) => (),
+            _ => return,
+        }
+        while let Some(ev) = gilrs.next_event() {
+            gilrs.update(&ev);
+        }
+        let input = gilrs.gamepads()
+            .next()
+            .map(|(_, gamepad)| {
+                let profile = preferences.profile(gamepad.uuid().as_bytes());
+                ::state::gamepad_input(gamepad, &profile, [true, true], preferences.aim_config(&profile))
+            })
+            .unwrap_or_default();
+
+        match session.tick(&input, &mut world) {
+            Ok(()) => (),
+            // The network may recover, a desync can't
+            Err(::error::NetError::Io(error)) => ::error::log(error),
+            Err(error) => {
+                ::error::log(error);
+                return;
+            }
+        }
+        if !session.connected() {
+            world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Wait);
+        }
+
+        graphics.render(&mut world);
+        ::wait_frame(&mut last_frame_instant);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn input(angle: f32) -> ::component::Input {
+        ::component::Input {
+            angle: Some(angle),
+            jump: true,
+            pressure: Some(0.3),
+        }
+    }
+
+    #[test]
+    fn input_round_trip() {
+        for &angle in [0.0, 1.0, -2.5, PI, -PI + 0.001, 3.0].iter() {
+            let output = NetInput::from_input(&input(angle)).to_input();
+            let error = ::wrap_angle(output.angle.unwrap() - angle).abs();
+            assert!(error <= 0.5 / ANGLE_SCALE + 1e-6, "angle {} came back as {:?}", angle, output.angle);
+            assert!(output.jump);
+            assert!((output.pressure.unwrap() - 0.3).abs() <= 0.5 / 255.0);
+        }
+    }
+
+    #[test]
+    fn quantized_input_is_stable() {
+        for &angle in [-32000, -1, 0, 1, 32000].iter() {
+            let net = NetInput {
+                angle: Some(angle),
+                jump: false,
+                pressure: Some(200),
+            };
+            assert_eq!(NetInput::from_input(&net.to_input()), net);
+        }
+    }
+
+    #[test]
+    fn angles_are_wrapped() {
+        for &angle in [0.5, -3.0, 3.1].iter() {
+            let wrapped = NetInput::from_input(&input(angle)).angle.unwrap() as i32;
+            let turned = NetInput::from_input(&input(angle + 2.0 * PI)).angle.unwrap() as i32;
+            assert!((wrapped - turned).abs() <= 1, "{} and {}", wrapped, turned);
+        }
+    }
+}
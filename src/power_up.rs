@@ -0,0 +1,117 @@
+//! Power-ups spawned in the arena with the power-ups rule.
+//!
+//! `PowerUpSystem` spawns pickups at `entity::PICKUP_SPOTS` and wears their effects off,
+//! `PhysicSystem` gives them to the balls touching them.
+
+/// Time in second between two pickups
+pub const SPAWN_INTERVAL: f32 = 5.0;
+/// Pickups waiting in the arena at most
+pub const MAX_PICKUPS: usize = 3;
+/// Time in second a power-up lasts
+pub const DURATION: f32 = 8.0;
+/// Density factor of a heavy ball
+pub const HEAVY_DENSITY: f32 = 4.0;
+/// Restitution of a bouncy ball
+pub const BOUNCY_RESTITUTION: f32 = 1.0;
+/// Radius factor of a shrunk ball
+pub const SHRINK_SCALE: f32 = 0.5;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerUp {
+    /// One more airjump at once
+    Airjump,
+    /// The ball weighs more and pushes the others
+    Heavy,
+    /// The ball bounces back without losing speed
+    Bouncy,
+    /// The ball is smaller
+    Shrink,
+    /// Gravity pulls the ball up
+    GravityFlip,
+}
+
+const POWER_UPS: [PowerUp; 5] = [
+    PowerUp::Airjump,
+    PowerUp::Heavy,
+    PowerUp::Bouncy,
+    PowerUp::Shrink,
+    PowerUp::GravityFlip,
+];
+
+impl PowerUp {
+    /// Power-up of the nth pickup of the round, pickups follow a fixed sequence so that every
+    /// peer spawns the same ones
+    pub fn nth(n: usize) -> Self {
+        POWER_UPS[(n * 3) % POWER_UPS.len()]
+    }
+
+    pub fn image(&self) -> ::Image {
+        match self {
+            PowerUp::Airjump => ::Image::PowerAirjump,
+            PowerUp::Heavy => ::Image::PowerHeavy,
+            PowerUp::Bouncy => ::Image::PowerBouncy,
+            PowerUp::Shrink => ::Image::PowerShrink,
+            PowerUp::GravityFlip => ::Image::PowerGravity,
+        }
+    }
+
+    /// Whether the power-up changes the body of the ball, which must then be replaced
+    pub fn reshapes(&self) -> bool {
+        match self {
+            PowerUp::Heavy | PowerUp::Bouncy | PowerUp::Shrink => true,
+            PowerUp::Airjump | PowerUp::GravityFlip => false,
+        }
+    }
+}
+
+/// Give a timed power-up to a ball, taking it again restarts its time
+pub fn add(power_ups: &mut ::component::PowerUps, power_up: PowerUp) {
+    power_ups.retain(|&(effect, _)| effect != power_up);
+    power_ups.push((power_up, DURATION));
+}
+
+/// Shape of a ball under its power-ups
+pub fn ball_shape(power_ups: &::component::PowerUps, handicap: &::component::Handicap) -> ::entity::BallShape {
+    let mut shape = ::entity::BallShape::new(handicap);
+    for &(power_up, _) in power_ups.iter() {
+        match power_up {
+            PowerUp::Heavy => shape.density *= HEAVY_DENSITY,
+            PowerUp::Bouncy => shape.restitution = BOUNCY_RESTITUTION,
+            PowerUp::Shrink => shape.radius *= SHRINK_SCALE,
+            PowerUp::Airjump | PowerUp::GravityFlip => (),
+        }
+    }
+    shape
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::component::{Handicap, PowerUps};
+    use ::entity::BallShape;
+
+    #[test]
+    fn shape_without_power_ups() {
+        let handicap = Handicap { size: 1.5, ..Handicap::default() };
+        assert_eq!(ball_shape(&PowerUps::default(), &handicap), BallShape::new(&handicap));
+    }
+
+    #[test]
+    fn shape_with_power_ups() {
+        let handicap = Handicap::default();
+        let base = BallShape::new(&handicap);
+        let power_ups = PowerUps(vec![(PowerUp::Heavy, 1.0), (PowerUp::Bouncy, 1.0), (PowerUp::Shrink, 1.0)]);
+        assert_eq!(ball_shape(&power_ups, &handicap), BallShape {
+            radius: base.radius * SHRINK_SCALE,
+            density: base.density * HEAVY_DENSITY,
+            restitution: BOUNCY_RESTITUTION,
+        });
+    }
+
+    #[test]
+    fn shape_ignores_other_power_ups() {
+        let handicap = Handicap::default();
+        let power_ups = PowerUps(vec![(PowerUp::Airjump, 1.0), (PowerUp::GravityFlip, 1.0)]);
+        assert_eq!(ball_shape(&power_ups, &handicap), BallShape::new(&handicap));
+    }
+}
@@ -0,0 +1,121 @@
+//! User preferences stored as JSON in the platform config directory:
+//! `$XDG_CONFIG_HOME/airjump-multi` (or `~/.config/airjump-multi`) on Linux,
+//! `~/Library/Application Support/airjump-multi` on macOS and `%APPDATA%\airjump-multi` on Windows.
+
+use serde_json;
+use std::env;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use error::PreferencesError;
+
+const DIRECTORY: &str = "airjump-multi";
+const FILE: &str = "preferences.json";
+
+pub const MAX_DEADZONE: f32 = 0.5;
+pub const DEADZONE_STEP: f32 = 0.05;
+pub const MAX_SMOOTHING: f32 = 0.9;
+pub const SMOOTHING_STEP: f32 = 0.1;
+/// Choices of maximal turn speed in radian per second, `None` for no limit
+pub const TURN_RATES: [Option<f32>; 4] = [
+    None,
+    Some(8.0 * ::std::f32::consts::PI),
+    Some(4.0 * ::std::f32::consts::PI),
+    Some(2.0 * ::std::f32::consts::PI),
+];
+/// Directions the aim can snap to, 0 for free aim
+pub const SNAPS: [usize; 3] = [0, 8, 16];
+
+/// Resource holding user preferences, states write it and `Graphics` and inputs read it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Preferences {
+    pub fullscreen: bool,
+    /// Aim of controllers without their own, edited by the settings opened from the keyboard
+    pub aim: ::binding::AimConfig,
+    /// Difficulty of the bots added in the lobby
+    pub bot_difficulty: ::component::Difficulty,
+    /// Bindings by controller UUID
+    pub profiles: BTreeMap<String, ::binding::Profile>,
+    /// Menu keys of the keyboard
+    pub keys: ::binding::KeyProfile,
+    /// Rules of the last match
+    pub rules: ::rules::Rules,
+    /// Deadzone saved before it moved to `aim`, only read to migrate older preferences
+    #[serde(rename = "deadzone", skip_serializing)]
+    legacy_deadzone: Option<f32>,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            fullscreen: true,
+            aim: ::binding::AimConfig::default(),
+            bot_difficulty: ::component::Difficulty::Normal,
+            profiles: BTreeMap::new(),
+            keys: ::binding::KeyProfile::default(),
+            rules: ::rules::Rules::default(),
+            legacy_deadzone: None,
+        }
+    }
+}
+
+fn config_directory() -> Option<PathBuf> {
+    let directory = if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library/Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+    directory.map(|directory| directory.join(DIRECTORY))
+}
+
+impl Preferences {
+    /// Bindings of the controller, the default ones if it has never been rebound
+    pub fn profile(&self, uuid: &[u8; 16]) -> ::binding::Profile {
+        self.profiles.get(&::binding::uuid_key(uuid)).cloned().unwrap_or_default()
+    }
+
+    /// Aim of the controller, the one last tuned by a player of this controller model or the one
+    /// of preferences
+    pub fn aim_config<'a>(&'a self, profile: &'a ::binding::Profile) -> &'a ::binding::AimConfig {
+        profile.aim_config.as_ref().unwrap_or(&self.aim)
+    }
+
+    /// Load preferences, missing or invalid preferences are replaced by defaults
+    pub fn load() -> Self {
+        match Preferences::try_load() {
+            Ok(preferences) => preferences,
+            Err(PreferencesError::Io(ref error)) if error.kind() == ::std::io::ErrorKind::NotFound => {
+                Preferences::default()
+            }
+            Err(error) => {
+                ::error::log(error);
+                Preferences::default()
+            }
+        }
+    }
+
+    fn try_load() -> Result<Self, PreferencesError> {
+        let path = config_directory().ok_or(PreferencesError::NoConfigDirectory)?.join(FILE);
+        let file = File::open(path)?;
+        let mut preferences: Preferences = serde_json::from_reader(file)?;
+        if let Some(deadzone) = preferences.legacy_deadzone.take() {
+            preferences.aim.deadzone = deadzone;
+        }
+        preferences.rules.clamp();
+        Ok(preferences)
+    }
+
+    pub fn save(&self) -> Result<(), PreferencesError> {
+        let directory = config_directory().ok_or(PreferencesError::NoConfigDirectory)?;
+        fs::create_dir_all(&directory)?;
+        let file = File::create(directory.join(FILE))?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
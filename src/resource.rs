@@ -3,6 +3,218 @@ pub struct UpdateTime(pub f32);
 #[derive(Deref, DerefMut)]
 pub struct DrawImage(pub Option<::Image>);
 
+/// Image drawn over the world for one frame
+pub struct Sprite {
+    pub image: ::Image,
+    pub position: [f32; 2],
+    pub height: f32,
+    pub color: [f32; 4],
+}
+
+#[derive(Deref, DerefMut, Default)]
+pub struct Sprites(pub Vec<Sprite>);
+
+impl Sprites {
+    /// Push a time in second with one decimal centered on the position
+    pub fn push_time(&mut self, seconds: f32, position: [f32; 2], height: f32) {
+        self.push_digits(&format!("{:.1}", seconds.max(0.0)), position, height);
+    }
+
+    /// Push a number centered on the position
+    pub fn push_number(&mut self, number: usize, position: [f32; 2], height: f32) {
+        self.push_digits(&number.to_string(), position, height);
+    }
+
+    fn push_digits(&mut self, text: &str, position: [f32; 2], height: f32) {
+        // Digit images are 9 pixels high and advance by 6
+        let advance = height * 6.0 / 9.0;
+        let start = position[0] - advance * (text.len() as f32 - 1.0) / 2.0;
+        for (i, c) in text.chars().enumerate() {
+            let image = match c {
+                '0' => ::Image::Digit0,
+                '1' => ::Image::Digit1,
+                '2' => ::Image::Digit2,
+                '3' => ::Image::Digit3,
+                '4' => ::Image::Digit4,
+                '5' => ::Image::Digit5,
+                '6' => ::Image::Digit6,
+                '7' => ::Image::Digit7,
+                '8' => ::Image::Digit8,
+                '9' => ::Image::Digit9,
+                _ => ::Image::DigitDot,
+            };
+            self.push(Sprite {
+                image,
+                position: [start + i as f32 * advance, position[1]],
+                height,
+                color: [1.0; 4],
+            });
+        }
+    }
+}
+
+pub const MAX_SLOTS: usize = 4;
+
+/// Seconds a disconnected controller has to reconnect before its slot is removed
+pub const DISCONNECT_TIMEOUT: u64 = 30;
+
+/// Colors of the slots, picked in the lobby, several slots can share a color
+pub const PLAYER_COLORS: [[f32; 4]; 6] = [
+    [1.0, 0.3, 0.3, 1.0],
+    [0.3, 0.5, 1.0, 1.0],
+    [0.3, 1.0, 0.4, 1.0],
+    [1.0, 0.9, 0.2, 1.0],
+    [0.9, 0.4, 1.0, 1.0],
+    [1.0, 0.6, 0.2, 1.0],
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SlotPlayer {
+    Gamepad(usize),
+    Bot(::component::Difficulty),
+}
+
+#[derive(Clone, Debug)]
+pub struct Slot {
+    /// Unique through the game, stored in the `Player` component of the balls of the slot
+    pub id: usize,
+    pub player: SlotPlayer,
+    /// Whether each part of the gamepad controls its own ball
+    pub two_balls: bool,
+    /// Index in `PLAYER_COLORS`
+    pub color: usize,
+    pub ready: bool,
+    /// The slot joined during a round and gets its balls at the next one
+    pub queued: bool,
+    /// Set while the controller of the slot is disconnected
+    pub disconnected: Option<Disconnection>,
+    /// Rounds won since the match started
+    pub score: u32,
+    /// Modifiers given to the balls of the slot
+    pub handicap: ::component::Handicap,
+    /// Aim tuned by the player in the settings, otherwise the one of their controller
+    pub aim: Option<::binding::AimConfig>,
+}
+
+/// Controller that left a slot, a controller with the same UUID and name can reclaim it
+#[derive(Clone, Debug)]
+pub struct Disconnection {
+    pub uuid: [u8; 16],
+    pub name: String,
+    pub since: ::std::time::Instant,
+}
+
+impl Slot {
+    pub fn ball_count(&self) -> usize {
+        if self.two_balls { 2 } else { 1 }
+    }
+}
+
+/// Players of the game, claimed in the lobby
+#[derive(Default)]
+pub struct Slots {
+    pub slots: Vec<Slot>,
+    next_id: usize,
+}
+
+impl Slots {
+    /// Add a slot with the first color not used, return `None` if all slots are taken
+    pub fn join(&mut self, player: SlotPlayer, queued: bool) -> Option<&mut Slot> {
+        if self.slots.len() >= MAX_SLOTS {
+            return None;
+        }
+        let color = (0..PLAYER_COLORS.len())
+            .find(|color| self.slots.iter().all(|slot| slot.color != *color))
+            .unwrap_or(0);
+        self.slots.push(Slot {
+            id: self.next_id,
+            player,
+            two_balls: false,
+            color,
+            ready: queued,
+            queued,
+            disconnected: None,
+            score: 0,
+            handicap: ::component::Handicap::default(),
+            aim: None,
+        });
+        self.next_id += 1;
+        self.slots.last_mut()
+    }
+
+    pub fn gamepad_slot(&self, gamepad_id: usize) -> Option<&Slot> {
+        self.slots.iter().find(|slot| slot.player == SlotPlayer::Gamepad(gamepad_id))
+    }
+
+    pub fn gamepad_slot_mut(&mut self, gamepad_id: usize) -> Option<&mut Slot> {
+        self.slots.iter_mut().find(|slot| slot.player == SlotPlayer::Gamepad(gamepad_id))
+    }
+
+    pub fn remove_gamepad(&mut self, gamepad_id: usize) {
+        self.slots.retain(|slot| slot.player != SlotPlayer::Gamepad(gamepad_id));
+    }
+
+    /// Keep the slot of the gamepad for its reconnection
+    pub fn disconnect(&mut self, gamepad_id: usize, uuid: [u8; 16], name: String) {
+        if let Some(slot) = self.gamepad_slot_mut(gamepad_id) {
+            slot.ready = false;
+            slot.disconnected = Some(Disconnection {
+                uuid,
+                name,
+                since: ::std::time::Instant::now(),
+            });
+        }
+    }
+
+    /// Give the first slot disconnected from a similar controller to the gamepad, return its id
+    pub fn reconnect(&mut self, gamepad_id: usize, uuid: [u8; 16], name: &str) -> Option<usize> {
+        let slot = self.slots.iter_mut().find(|slot| {
+            slot.disconnected.as_ref()
+                .map(|disconnection| disconnection.uuid == uuid && disconnection.name == name)
+                .unwrap_or(false)
+        })?;
+        slot.player = SlotPlayer::Gamepad(gamepad_id);
+        slot.disconnected = None;
+        Some(slot.id)
+    }
+
+    pub fn any_disconnected(&self) -> bool {
+        self.slots.iter().any(|slot| slot.disconnected.is_some())
+    }
+
+    /// Remove slots disconnected for more than `DISCONNECT_TIMEOUT`, return their ids
+    pub fn remove_expired(&mut self) -> Vec<usize> {
+        let timeout = ::std::time::Duration::from_secs(DISCONNECT_TIMEOUT);
+        let expired = self.slots.iter()
+            .filter(|slot| {
+                slot.disconnected.as_ref()
+                    .map(|disconnection| disconnection.since.elapsed() >= timeout)
+                    .unwrap_or(false)
+            })
+            .map(|slot| slot.id)
+            .collect::<Vec<_>>();
+        self.slots.retain(|slot| !expired.contains(&slot.id));
+        expired
+    }
+
+    /// Whether every slot is ready, there must be at least one slot
+    pub fn all_ready(&self) -> bool {
+        !self.slots.is_empty() && self.slots.iter().all(|slot| slot.ready)
+    }
+
+    /// Number of balls of slots playing the current round
+    pub fn ball_count(&self) -> usize {
+        self.slots.iter()
+            .filter(|slot| !slot.queued)
+            .map(|slot| slot.ball_count())
+            .sum()
+    }
+}
+
+/// UUID of connected gamepads, used to find their bindings
+#[derive(Deref, DerefMut, Default)]
+pub struct GamepadUuids(pub ::fnv::FnvHashMap<usize, [u8; 16]>);
+
 pub type PhysicWorld = ::nphysics2d::world::World<f32>;
 
 #[derive(Deref, DerefMut)]
@@ -12,4 +224,97 @@ impl BodiesMap {
     pub fn new() -> Self {
         BodiesMap(::fnv::FnvHashMap::default())
     }
+
+    /// Check that every mapped body exists in the physic world and every body holding a
+    /// collider is mapped.
+    ///
+    /// Mapped bodies missing from the physic world are removed from the map.
+    pub fn validate(&mut self, physic_world: &PhysicWorld) -> Vec<::error::PhysicError> {
+        let mut errors = vec![];
+
+        let missing = self.keys()
+            .filter(|handle| !handle.is_ground() && physic_world.rigid_body(**handle).is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+        for handle in missing {
+            self.remove(&handle);
+            errors.push(::error::PhysicError::MissingBody(handle));
+        }
+
+        for collision_object in physic_world.collision_world().collision_objects() {
+            let handle = collision_object.data().body();
+            let error = ::error::PhysicError::UnmappedBody(handle);
+            if !self.contains_key(&handle) && !errors.contains(&error) {
+                errors.push(error);
+            }
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn push_time_layout() {
+        let mut sprites = Sprites::default();
+        sprites.push_time(12.34, [1.0, 2.0], 9.0);
+        let images = sprites.iter().map(|sprite| sprite.image).collect::<Vec<_>>();
+        assert!(images == vec![::Image::Digit1, ::Image::Digit2, ::Image::DigitDot, ::Image::Digit3]);
+        // Characters advance by 6 for a height of 9, centered on the position
+        let xs = sprites.iter().map(|sprite| sprite.position[0]).collect::<Vec<_>>();
+        assert_eq!(xs, vec![-8.0, -2.0, 4.0, 10.0]);
+        assert!(sprites.iter().all(|sprite| sprite.position[1] == 2.0 && sprite.height == 9.0));
+    }
+
+    #[test]
+    fn push_negative_time() {
+        let mut sprites = Sprites::default();
+        sprites.push_time(-1.0, [0.0, 0.0], 9.0);
+        let images = sprites.iter().map(|sprite| sprite.image).collect::<Vec<_>>();
+        assert!(images == vec![::Image::Digit0, ::Image::DigitDot, ::Image::Digit0]);
+    }
+
+    const UUID: [u8; 16] = [7; 16];
+
+    fn disconnected_slots() -> Slots {
+        let mut slots = Slots::default();
+        slots.join(SlotPlayer::Gamepad(0), false);
+        slots.join(SlotPlayer::Gamepad(1), false);
+        slots.disconnect(0, UUID, "pad".to_string());
+        slots
+    }
+
+    #[test]
+    fn reconnect_same_controller() {
+        let mut slots = disconnected_slots();
+        assert!(slots.any_disconnected());
+        assert_eq!(slots.reconnect(2, UUID, "pad"), Some(0));
+        assert_eq!(slots.slots[0].player, SlotPlayer::Gamepad(2));
+        assert!(!slots.any_disconnected());
+    }
+
+    #[test]
+    fn reconnect_other_controller() {
+        let mut slots = disconnected_slots();
+        assert_eq!(slots.reconnect(2, UUID, "other"), None);
+        assert_eq!(slots.reconnect(2, [8; 16], "pad"), None);
+        assert!(slots.any_disconnected());
+    }
+
+    #[test]
+    fn remove_expired_slots() {
+        let mut slots = disconnected_slots();
+        assert!(slots.remove_expired().is_empty());
+        assert_eq!(slots.slots.len(), 2);
+
+        slots.slots[0].disconnected.as_mut().unwrap().since =
+            Instant::now() - Duration::from_secs(DISCONNECT_TIMEOUT + 1);
+        assert_eq!(slots.remove_expired(), vec![0]);
+        assert_eq!(slots.slots.len(), 1);
+        assert_eq!(slots.slots[0].id, 1);
+    }
 }
@@ -0,0 +1,251 @@
+//! Rules of a match, chosen in the lobby and saved in preferences.
+//!
+//! Network matches and the gym use the default rules unless told otherwise so that every peer
+//! simulates the same game.
+
+/// Fraction of `BALL_VELOCITY` of the weakest jump
+pub const MIN_JUMP_STRENGTH: f32 = 0.3;
+/// Time in second to hold the jump button to charge a full jump
+pub const CHARGE_TIME: f32 = 0.6;
+/// Time in second the pressure of an analog trigger is watched before jumping with its peak
+pub const ANALOG_WINDOW: f32 = 0.05;
+pub const MAX_AIRJUMPS: u32 = 5;
+/// Choices of time in second to regenerate an airjump, `None` for no regeneration
+pub const AIRJUMP_REGENS: [Option<f32>; 4] = [None, Some(1.0), Some(2.0), Some(4.0)];
+pub const MAX_GONGS: u32 = 3;
+pub const MAX_GONG_HITS: u32 = 5;
+/// Choices of relative speed a ball must hit a gong with for the hit to count, `None` for any contact
+pub const GONG_SPEEDS: [Option<f32>; 3] = [None, Some(0.8), Some(1.2)];
+/// Choices of length in second of a round, `None` without time limit
+pub const ROUND_TIMES: [Option<f32>; 5] = [None, Some(30.0), Some(60.0), Some(120.0), Some(180.0)];
+
+/// Objective of the rounds, see `mode`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// The first ball touching the gong wins
+    Gong,
+    /// The first player spending `HILL_TIME` in the hill wins
+    KingOfTheHill,
+    /// "It" is passed by contact, the player who is "it" when the time is over loses
+    Tag,
+    /// Balls touching the ground or spikes or leaving the arena are out, the last player standing wins
+    Survival,
+    /// The first player passing every checkpoint in order then touching the gong wins
+    Race,
+}
+
+impl Mode {
+    pub fn next(&self, forward: bool) -> Self {
+        const MODES: [Mode; 5] = [Mode::Gong, Mode::KingOfTheHill, Mode::Tag, Mode::Survival, Mode::Race];
+        let index = MODES.iter().position(|mode| mode == self).unwrap();
+        if forward {
+            MODES[(index + 1) % MODES.len()]
+        } else {
+            MODES[(index + MODES.len() - 1) % MODES.len()]
+        }
+    }
+}
+
+/// How the strength of a jump is chosen
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JumpStrength {
+    /// Every jump is full strength
+    Fixed,
+    /// Strength follows the peak pressure of the trigger during `ANALOG_WINDOW`
+    Analog,
+    /// Strength grows while the button is held, the ball jumps when it is released
+    Charged,
+}
+
+/// What a jump does to the velocity of the ball
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JumpVelocity {
+    /// The jump replaces the velocity
+    Reset,
+    /// The jump is added to the velocity
+    Add,
+}
+
+/// How gongs are held in the arena
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GongMount {
+    /// Pulled back to its place by a spring
+    Spring,
+    /// Swings like a pendulum from a point above it
+    Pendulum,
+    /// Slides along a horizontal rail
+    Rail,
+    /// Moves back and forth on its own
+    Moving,
+}
+
+impl GongMount {
+    pub fn next(&self, forward: bool) -> Self {
+        const MOUNTS: [GongMount; 4] = [GongMount::Spring, GongMount::Pendulum, GongMount::Rail, GongMount::Moving];
+        let index = MOUNTS.iter().position(|mount| mount == self).unwrap();
+        if forward {
+            MOUNTS[(index + 1) % MOUNTS.len()]
+        } else {
+            MOUNTS[(index + MOUNTS.len() - 1) % MOUNTS.len()]
+        }
+    }
+}
+
+/// Gongs of the arena and what it takes to ring them
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct GongRules {
+    pub count: u32,
+    pub mount: GongMount,
+    /// Hits a player needs to win the round in gong mode
+    pub hits: u32,
+    /// Relative speed of a hit to count, `None` if any contact counts
+    pub min_speed: Option<f32>,
+}
+
+impl Default for GongRules {
+    fn default() -> Self {
+        GongRules {
+            count: 1,
+            mount: GongMount::Spring,
+            hits: 1,
+            min_speed: None,
+        }
+    }
+}
+
+/// Resource holding the rules of the match
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Rules {
+    pub mode: Mode,
+    pub jump_strength: JumpStrength,
+    pub jump_velocity: JumpVelocity,
+    /// Airjumps a ball can store
+    pub max_airjumps: u32,
+    /// Time in second to regenerate an airjump, `None` if airjumps only come back on contact
+    pub airjump_regen: Option<f32>,
+    /// Whether only the ground restores airjumps, otherwise other balls do too
+    pub ground_restore_only: bool,
+    /// Whether power-ups appear in the arena, see `power_up`
+    pub power_ups: bool,
+    /// Whether the arena has platforms, bumpers, wind and spikes
+    pub hazards: bool,
+    /// Whether balls stomp and knock each other back, see `combat`
+    pub combat: bool,
+    pub gong: GongRules,
+    /// Length in second of a round, `None` if only the mode ends it
+    pub round_time: Option<f32>,
+    /// Whether a round tied when the time is up goes on until a player takes the lead, otherwise
+    /// it is a draw
+    pub sudden_death: bool,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            mode: Mode::Gong,
+            jump_strength: JumpStrength::Fixed,
+            jump_velocity: JumpVelocity::Reset,
+            max_airjumps: 1,
+            airjump_regen: None,
+            ground_restore_only: false,
+            power_ups: false,
+            hazards: false,
+            combat: false,
+            gong: GongRules::default(),
+            round_time: None,
+            sudden_death: true,
+        }
+    }
+}
+
+impl Rules {
+    /// Bring numbers edited outside of the menus, in preferences or gym requests, within their
+    /// menu bounds
+    pub fn clamp(&mut self) {
+        self.max_airjumps = self.max_airjumps.max(1).min(MAX_AIRJUMPS);
+        self.gong.count = self.gong.count.max(1).min(MAX_GONGS);
+        self.gong.hits = self.gong.hits.max(1).min(MAX_GONG_HITS);
+    }
+}
+
+impl JumpStrength {
+    pub fn next(&self, forward: bool) -> Self {
+        match (self, forward) {
+            (JumpStrength::Fixed, true) | (JumpStrength::Charged, false) => JumpStrength::Analog,
+            (JumpStrength::Analog, true) | (JumpStrength::Fixed, false) => JumpStrength::Charged,
+            (JumpStrength::Charged, true) | (JumpStrength::Analog, false) => JumpStrength::Fixed,
+        }
+    }
+}
+
+impl JumpVelocity {
+    pub fn next(&self) -> Self {
+        match self {
+            JumpVelocity::Reset => JumpVelocity::Add,
+            JumpVelocity::Add => JumpVelocity::Reset,
+        }
+    }
+}
+
+/// Speed of a jump of the given strength between 0 and 1
+pub fn jump_speed(strength: f32) -> f32 {
+    let strength = strength.max(0.0).min(1.0);
+    ::entity::BALL_VELOCITY * (MIN_JUMP_STRENGTH + (1.0 - MIN_JUMP_STRENGTH) * strength)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jump_speed_bounds() {
+        let velocity = ::entity::BALL_VELOCITY;
+        assert!((jump_speed(0.0) - velocity * MIN_JUMP_STRENGTH).abs() < 1e-6);
+        assert!((jump_speed(1.0) - velocity).abs() < 1e-6);
+        assert_eq!(jump_speed(-1.0), jump_speed(0.0));
+        assert_eq!(jump_speed(2.0), jump_speed(1.0));
+    }
+
+    #[test]
+    fn jump_speed_grows_with_strength() {
+        assert!(jump_speed(0.25) < jump_speed(0.5));
+        assert!(jump_speed(0.5) < jump_speed(0.75));
+    }
+
+    #[test]
+    fn mode_cycle() {
+        assert_eq!(Mode::Gong.next(true), Mode::KingOfTheHill);
+        assert_eq!(Mode::Race.next(true), Mode::Gong);
+        assert_eq!(Mode::Gong.next(false), Mode::Race);
+        let mut mode = Mode::Tag;
+        for _ in 0..5 {
+            mode = mode.next(true);
+        }
+        assert_eq!(mode, Mode::Tag);
+    }
+
+    #[test]
+    fn gong_mount_cycle() {
+        assert_eq!(GongMount::Spring.next(true), GongMount::Pendulum);
+        assert_eq!(GongMount::Pendulum.next(true), GongMount::Rail);
+        assert_eq!(GongMount::Rail.next(true), GongMount::Moving);
+        assert_eq!(GongMount::Moving.next(true), GongMount::Spring);
+        assert_eq!(GongMount::Spring.next(false), GongMount::Moving);
+    }
+
+    #[test]
+    fn clamp_gong_rules() {
+        let mut rules = Rules::default();
+        rules.gong.count = 0;
+        rules.gong.hits = MAX_GONG_HITS + 1;
+        rules.clamp();
+        assert_eq!(rules.gong.count, 1);
+        assert_eq!(rules.gong.hits, MAX_GONG_HITS);
+    }
+}
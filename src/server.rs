@@ -0,0 +1,817 @@
+//! Authoritative server and thin clients.
+//!
+//! The server runs the simulation without window, each client sends the input of its first
+//! gamepad and receives snapshots of the world. Snapshots are delta compressed against the last
+//! snapshot acknowledged by the client and clients render an interpolation between the two
+//! snapshots around `INTERPOLATION_DELAY` frames in the past.
+//!
+//! Spectators receive the same snapshots but send no input. They can watch a dedicated server
+//! or a local game started with `--stream <address>`, and can join at any time as their first
+//! snapshot is a full state.
+//!
+//! On localhost:
+//! ```sh
+//! airjump-multi --server 127.0.0.1:7100
+//! airjump-multi --connect 127.0.0.1:7100 --net-bind 127.0.0.1:0
+//! airjump-multi --spectate 127.0.0.1:7100 --net-bind 127.0.0.1:0
+//! ```
+
+use specs;
+use specs::Join;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use net::{NetInput, Transport};
+use snapshot::BodyState;
+
+/// A snapshot is sent every `SNAPSHOT_INTERVAL` frames
+pub const SNAPSHOT_INTERVAL: u32 = 2;
+/// Number of frames clients render in the past
+pub const INTERPOLATION_DELAY: u32 = 6;
+/// Clients that didn't send anything for this number of seconds are removed
+pub const CLIENT_TIMEOUT: u64 = 5;
+
+/// Number of snapshots kept to compute deltas
+const HISTORY_LENGTH: usize = 64;
+
+/// Server entity id and generation of a ball, a ball created in place of a deleted one is new
+pub type BallId = (u32, i32);
+
+fn ball_id(entity: specs::Entity) -> BallId {
+    (entity.id(), entity.gen().id())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BallState {
+    pub body: BodyState,
+    pub airjump: ::component::Airjump,
+    /// Radius changed by handicaps and power-ups
+    pub radius: f32,
+    /// Color of the slot owning the ball
+    pub tint: [f32; 4],
+    /// Whether the ball is "it" in tag mode
+    pub it: bool,
+}
+
+/// What clients show of the world: balls, gongs, moving platforms, pickups and what the mode
+/// draws on top of the game
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorldState {
+    pub balls: BTreeMap<BallId, BallState>,
+    pub gongs: Vec<BodyState>,
+    /// Moving platforms ordered by their first point, other hazards don't move
+    pub platforms: Vec<BodyState>,
+    pub pickups: Vec<::component::Pickup>,
+    /// Rules tell which mode to draw and whether the arena has hazards
+    pub rules: ::rules::Rules,
+    pub timer: ::mode::RoundTimer,
+}
+
+/// Changes between two world states
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Delta {
+    pub frame: u32,
+    /// Frame of the state this delta applies to, `None` if it is a full state
+    pub base: Option<u32>,
+    pub changed: Vec<(BallId, BallState)>,
+    pub removed: Vec<BallId>,
+    /// Each of the following is `None` if it didn't change
+    pub gongs: Option<Vec<BodyState>>,
+    pub platforms: Option<Vec<BodyState>>,
+    pub pickups: Option<Vec<::component::Pickup>>,
+    pub rules: Option<::rules::Rules>,
+    pub timer: Option<::mode::RoundTimer>,
+}
+
+/// `value` if it differs from `base`
+fn changed<T: Clone + PartialEq>(value: &T, base: Option<&T>) -> Option<T> {
+    if base == Some(value) {
+        None
+    } else {
+        Some(value.clone())
+    }
+}
+
+/// Moving platforms in the same order on the server and the clients
+fn platform_bodies(world: &specs::World) -> Vec<::component::RigidBody> {
+    let hazards = world.read_storage::<::component::Hazard>();
+    let paths = world.read_storage::<::component::Path>();
+    let bodies = world.read_storage::<::component::RigidBody>();
+    let mut platforms = (&hazards, &paths, &bodies).join()
+        .map(|(_, path, body)| (path.points[0], body.clone()))
+        .collect::<Vec<_>>();
+    platforms.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(::std::cmp::Ordering::Equal));
+    platforms.into_iter().map(|(_, body)| body).collect()
+}
+
+impl WorldState {
+    pub fn take(world: &specs::World) -> Self {
+        let entities = world.entities();
+        let inputs = world.read_storage::<::component::Input>();
+        let airjumps = world.read_storage::<::component::Airjump>();
+        let images = world.read_storage::<::component::Image>();
+        let tints = world.read_storage::<::component::Tint>();
+        let its = world.read_storage::<::component::It>();
+        let gongs = world.read_storage::<::component::Gong>();
+        let pickups = world.read_storage::<::component::Pickup>();
+        let bodies = world.read_storage::<::component::RigidBody>();
+        let physic_world = world.read_resource::<::resource::PhysicWorld>();
+
+        let balls = (&*entities, &inputs, &airjumps, &images, &bodies).join()
+            .filter_map(|(entity, _, airjump, image, body)| {
+                let body = body.get(&physic_world).ok()?;
+                Some((ball_id(entity), BallState {
+                    body: BodyState::from_body(body),
+                    airjump: *airjump,
+                    radius: image.0,
+                    tint: tints.get(entity).map(|tint| tint.0).unwrap_or([1.0; 4]),
+                    it: its.get(entity).is_some(),
+                }))
+            })
+            .collect();
+
+        let gongs = (&gongs, &bodies).join()
+            .filter_map(|(_, body)| body.get(&physic_world).ok())
+            .map(BodyState::from_body)
+            .collect();
+
+        let platforms = platform_bodies(world).iter()
+            .filter_map(|body| body.get(&physic_world).ok())
+            .map(BodyState::from_body)
+            .collect();
+
+        WorldState {
+            balls,
+            gongs,
+            platforms,
+            pickups: pickups.join().cloned().collect(),
+            rules: world.read_resource::<::rules::Rules>().clone(),
+            timer: world.read_resource::<::mode::RoundTimer>().clone(),
+        }
+    }
+
+    /// Changes from `base` to `self`, everything is sent if there is no base
+    pub fn delta(&self, frame: u32, base: Option<(u32, &WorldState)>) -> Delta {
+        let base_state = base.map(|(_, state)| state);
+        Delta {
+            frame,
+            base: base.map(|(base_frame, _)| base_frame),
+            changed: self.balls.iter()
+                .filter(|&(id, ball)| base_state.and_then(|base| base.balls.get(id)) != Some(ball))
+                .map(|(id, ball)| (*id, ball.clone()))
+                .collect(),
+            removed: base_state
+                .map(|base| {
+                    base.balls.keys()
+                        .filter(|id| !self.balls.contains_key(id))
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default(),
+            gongs: changed(&self.gongs, base_state.map(|base| &base.gongs)),
+            platforms: changed(&self.platforms, base_state.map(|base| &base.platforms)),
+            pickups: changed(&self.pickups, base_state.map(|base| &base.pickups)),
+            rules: changed(&self.rules, base_state.map(|base| &base.rules)),
+            timer: changed(&self.timer, base_state.map(|base| &base.timer)),
+        }
+    }
+
+    /// Apply a delta to its base
+    pub fn apply(base: Option<&WorldState>, delta: &Delta) -> WorldState {
+        let mut state = base.cloned().unwrap_or_default();
+        for id in &delta.removed {
+            state.balls.remove(id);
+        }
+        for &(id, ref ball) in &delta.changed {
+            state.balls.insert(id, ball.clone());
+        }
+        if let Some(ref gongs) = delta.gongs {
+            state.gongs = gongs.clone();
+        }
+        if let Some(ref platforms) = delta.platforms {
+            state.platforms = platforms.clone();
+        }
+        if let Some(ref pickups) = delta.pickups {
+            state.pickups = pickups.clone();
+        }
+        if let Some(ref rules) = delta.rules {
+            state.rules = rules.clone();
+        }
+        if let Some(ref timer) = delta.timer {
+            state.timer = timer.clone();
+        }
+        state
+    }
+
+    /// State between `self` (`t` = 0) and `next` (`t` = 1), balls missing in `next` are kept,
+    /// what isn't a body is the one of `self`
+    pub fn interpolate(&self, next: &WorldState, t: f32) -> WorldState {
+        let bodies = |bodies: &[BodyState], next: &[BodyState]| -> Vec<BodyState> {
+            bodies.iter()
+                .zip(next.iter())
+                .map(|(body, next)| body.interpolate(next, t))
+                .collect()
+        };
+        WorldState {
+            balls: self.balls.iter()
+                .map(|(id, ball)| {
+                    let body = match next.balls.get(id) {
+                        Some(next) => ball.body.interpolate(&next.body, t),
+                        None => ball.body.clone(),
+                    };
+                    (*id, BallState { body, ..ball.clone() })
+                })
+                .collect(),
+            gongs: bodies(&self.gongs, &next.gongs),
+            platforms: bodies(&self.platforms, &next.platforms),
+            pickups: self.pickups.clone(),
+            rules: self.rules.clone(),
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ClientMessage {
+    Connect,
+    Spectate,
+    Input {
+        input: NetInput,
+        /// Last snapshot frame received
+        ack: Option<u32>,
+    },
+    /// Last snapshot frame received by a spectator
+    Ack(Option<u32>),
+    Disconnect,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub enum ServerMessage {
+    /// Answer to connect with the id of the ball of the client, or `None` for a spectator
+    Welcome {
+        ball: Option<BallId>,
+    },
+    Snapshot(Delta),
+}
+
+struct Client {
+    /// Ball of the client, spectators have none
+    entity: Option<specs::Entity>,
+    acked: Option<u32>,
+    last_seen: Instant,
+}
+
+impl Client {
+    fn new(entity: Option<specs::Entity>) -> Self {
+        Client {
+            entity,
+            acked: None,
+            last_seen: Instant::now(),
+        }
+    }
+}
+
+/// Snapshots recently sent, to compute deltas against the ones acknowledged
+struct History(VecDeque<(u32, WorldState)>);
+
+impl History {
+    fn new() -> Self {
+        History(VecDeque::new())
+    }
+
+    fn delta(&self, state: &WorldState, frame: u32, acked: Option<u32>) -> Delta {
+        let base = acked.and_then(|acked| {
+            self.0.iter()
+                .find(|&&(frame, _)| frame == acked)
+                .map(|&(frame, ref state)| (frame, state))
+        });
+        state.delta(frame, base)
+    }
+
+    fn push(&mut self, frame: u32, state: WorldState) {
+        self.0.push_back((frame, state));
+        while self.0.len() > HISTORY_LENGTH {
+            self.0.pop_front();
+        }
+    }
+}
+
+/// Send a snapshot of the world to every client, a client that can't be reached is logged and
+/// the others still get theirs
+fn send_snapshots(
+    world: &specs::World,
+    frame: u32,
+    clients: &HashMap<SocketAddr, Client>,
+    history: &mut History,
+    transport: &mut Transport,
+) {
+    let state = WorldState::take(world);
+    for (address, client) in clients {
+        let delta = history.delta(&state, frame, client.acked);
+        if let Err(error) = transport.send(&ServerMessage::Snapshot(delta), *address) {
+            ::error::log(error);
+        }
+    }
+    history.push(frame, state);
+}
+
+/// Addresses of clients that didn't send anything for too long
+fn timed_out(clients: &HashMap<SocketAddr, Client>) -> Vec<SocketAddr> {
+    clients.iter()
+        .filter(|&(_, client)| client.last_seen.elapsed() > Duration::from_secs(CLIENT_TIMEOUT))
+        .map(|(address, _)| *address)
+        .collect()
+}
+
+pub struct Server<'a, 'b> {
+    transport: Transport,
+    dispatcher: specs::Dispatcher<'a, 'b>,
+    clients: HashMap<SocketAddr, Client>,
+    history: History,
+    frame: u32,
+}
+
+impl<'a, 'b> Server<'a, 'b> {
+    pub fn new(transport: Transport) -> Self {
+        Server {
+            transport,
+            dispatcher: ::create_update_dispatcher(),
+            clients: HashMap::new(),
+            history: History::new(),
+            frame: 0,
+        }
+    }
+
+    pub fn tick(&mut self, world: &mut specs::World) -> io::Result<()> {
+        self.receive(world)?;
+        self.remove_timed_out(world);
+
+        world.write_resource::<::resource::UpdateTime>().0 = ::FIXED_STEP;
+        self.dispatcher.dispatch(&mut world.res);
+        ::safe_maintain(world);
+        self.frame += 1;
+
+        if self.frame % SNAPSHOT_INTERVAL == 0 {
+            send_snapshots(world, self.frame, &self.clients, &mut self.history, &mut self.transport);
+        }
+        Ok(())
+    }
+
+    fn receive(&mut self, world: &mut specs::World) -> io::Result<()> {
+        for (message, address) in self.transport.receive::<ClientMessage>()? {
+            match message {
+                ClientMessage::Connect => {
+                    let entity = match self.clients.get_mut(&address) {
+                        Some(client) => {
+                            client.last_seen = Instant::now();
+                            client.entity
+                        }
+                        None => {
+                            let entity = Some(::entity::create_ball_entity(world));
+                            self.clients.insert(address, Client::new(entity));
+                            entity
+                        }
+                    };
+                    let ball = entity.map(ball_id);
+                    if let Err(error) = self.transport.send(&ServerMessage::Welcome { ball }, address) {
+                        ::error::log(error);
+                    }
+                }
+                ClientMessage::Spectate => {
+                    self.clients.entry(address).or_insert(Client::new(None)).last_seen = Instant::now();
+                    if let Err(error) = self.transport.send(&ServerMessage::Welcome { ball: None }, address) {
+                        ::error::log(error);
+                    }
+                }
+                ClientMessage::Input { input, ack } => {
+                    if let Some(client) = self.clients.get_mut(&address) {
+                        client.last_seen = Instant::now();
+                        client.acked = client.acked.max(ack);
+                        if let Some(entity) = client.entity {
+                            if let Some(component) = world.write_storage::<::component::Input>().get_mut(entity) {
+                                *component = input.to_input();
+                            }
+                        }
+                    }
+                }
+                ClientMessage::Ack(ack) => {
+                    if let Some(client) = self.clients.get_mut(&address) {
+                        client.last_seen = Instant::now();
+                        client.acked = client.acked.max(ack);
+                    }
+                }
+                ClientMessage::Disconnect => {
+                    if let Some(entity) = self.clients.remove(&address).and_then(|client| client.entity) {
+                        // The ball may already be gone
+                        if let Err(error) = world.entities().delete(entity) {
+                            ::error::log(error);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn remove_timed_out(&mut self, world: &mut specs::World) {
+        for address in timed_out(&self.clients) {
+            if let Some(entity) = self.clients.remove(&address).and_then(|client| client.entity) {
+                if let Err(error) = world.entities().delete(entity) {
+                    ::error::log(error);
+                }
+            }
+        }
+    }
+}
+
+/// Stream a local game to spectators
+pub struct Streamer {
+    transport: Transport,
+    spectators: HashMap<SocketAddr, Client>,
+    history: History,
+    frame: u32,
+}
+
+impl Streamer {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        Ok(Streamer {
+            transport: Transport::bind(address, None)?,
+            spectators: HashMap::new(),
+            history: History::new(),
+            frame: 0,
+        })
+    }
+
+    /// Handle spectators messages and send them a snapshot of the world
+    pub fn tick(&mut self, world: &specs::World) -> io::Result<()> {
+        for (message, address) in self.transport.receive::<ClientMessage>()? {
+            match message {
+                ClientMessage::Connect | ClientMessage::Spectate => {
+                    self.spectators.entry(address).or_insert(Client::new(None)).last_seen = Instant::now();
+                    if let Err(error) = self.transport.send(&ServerMessage::Welcome { ball: None }, address) {
+                        ::error::log(error);
+                    }
+                }
+                ClientMessage::Input { ack, .. } | ClientMessage::Ack(ack) => {
+                    if let Some(spectator) = self.spectators.get_mut(&address) {
+                        spectator.last_seen = Instant::now();
+                        spectator.acked = spectator.acked.max(ack);
+                    }
+                }
+                ClientMessage::Disconnect => {
+                    self.spectators.remove(&address);
+                }
+            }
+        }
+        for address in timed_out(&self.spectators) {
+            self.spectators.remove(&address);
+        }
+
+        self.frame += 1;
+        if self.frame % SNAPSHOT_INTERVAL == 0 {
+            send_snapshots(world, self.frame, &self.spectators, &mut self.history, &mut self.transport);
+        }
+        Ok(())
+    }
+}
+
+/// Client side: received states and entities created to render them
+pub struct ClientView {
+    /// Reconstructed states by frame
+    states: VecDeque<(u32, WorldState)>,
+    /// Local entity of each server ball
+    entities: HashMap<BallId, specs::Entity>,
+    /// Frame rendered, in the server timeline
+    render_frame: f32,
+}
+
+impl ClientView {
+    pub fn new() -> Self {
+        ClientView {
+            states: VecDeque::new(),
+            entities: HashMap::new(),
+            render_frame: 0.0,
+        }
+    }
+
+    pub fn last_frame(&self) -> Option<u32> {
+        self.states.back().map(|&(frame, _)| frame)
+    }
+
+    pub fn receive(&mut self, delta: &Delta) {
+        if self.last_frame().map(|last| delta.frame <= last).unwrap_or(false) {
+            return;
+        }
+        let base = match delta.base {
+            Some(base_frame) => match self.states.iter().find(|&&(frame, _)| frame == base_frame) {
+                Some(&(_, ref base)) => Some(base),
+                // Base is too old, wait for a delta against a more recent state
+                None => return,
+            },
+            None => None,
+        };
+        let state = WorldState::apply(base, delta);
+        self.states.push_back((delta.frame, state));
+        while self.states.len() > HISTORY_LENGTH {
+            self.states.pop_front();
+        }
+    }
+
+    /// Advance one frame and show the interpolated state in the world
+    pub fn update(&mut self, world: &mut specs::World) {
+        let last = match self.last_frame() {
+            Some(last) => last as f32,
+            None => return,
+        };
+        let target = last - INTERPOLATION_DELAY as f32;
+        // Follow the server pace, and catch up if too far from it
+        self.render_frame = if (self.render_frame - target).abs() > INTERPOLATION_DELAY as f32 {
+            target
+        } else {
+            self.render_frame + 1.0
+        };
+
+        let state = {
+            let next_index = self.states.iter().position(|&(frame, _)| frame as f32 > self.render_frame);
+            match next_index {
+                Some(0) => self.states.front().unwrap().1.clone(),
+                None => self.states.back().unwrap().1.clone(),
+                Some(next_index) => {
+                    let (previous_frame, ref previous) = self.states[next_index - 1];
+                    let (next_frame, ref next) = self.states[next_index];
+                    let t = (self.render_frame - previous_frame as f32) / (next_frame - previous_frame) as f32;
+                    previous.interpolate(next, t)
+                }
+            }
+        };
+        self.show(&state, world);
+    }
+
+    fn show(&mut self, state: &WorldState, world: &mut specs::World) {
+        // Follow the rules of the server: the mode drawn, its gongs and whether the arena has hazards
+        ::entity::set_rules(&state.rules, world);
+
+        let removed = self.entities.keys()
+            .filter(|id| !state.balls.contains_key(id))
+            .cloned()
+            .collect::<Vec<_>>();
+        for id in removed {
+            if let Some(entity) = self.entities.remove(&id) {
+                if let Err(error) = world.entities().delete(entity) {
+                    ::error::log(error);
+                }
+            }
+        }
+        for id in state.balls.keys() {
+            if !self.entities.contains_key(id) {
+                let entity = ::entity::create_ball_entity(world);
+                self.entities.insert(*id, entity);
+            }
+        }
+        ::safe_maintain(world);
+        *world.write_resource::<::mode::RoundTimer>() = state.timer.clone();
+
+        {
+            let mut images = world.write_storage::<::component::Image>();
+            let mut tints = world.write_storage::<::component::Tint>();
+            let mut its = world.write_storage::<::component::It>();
+            for (id, ball) in &state.balls {
+                let entity = self.entities[id];
+                if let Some(image) = images.get_mut(entity) {
+                    image.0 = ball.radius;
+                }
+                tints.insert(entity, ::component::Tint(ball.tint));
+                if ball.it {
+                    its.insert(entity, ::component::It);
+                } else {
+                    its.remove(entity);
+                }
+            }
+
+            let gongs = world.read_storage::<::component::Gong>();
+            let bodies = world.read_storage::<::component::RigidBody>();
+            let platforms = platform_bodies(world);
+            let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
+            for (id, ball) in &state.balls {
+                if let Some(body) = bodies.get(self.entities[id]) {
+                    match body.get_mut(&mut physic_world) {
+                        Ok(body) => ball.body.apply(body),
+                        Err(error) => ::error::log(error),
+                    }
+                }
+            }
+            let gongs = (&gongs, &bodies).join().map(|(_, body)| body).zip(state.gongs.iter());
+            for (body, body_state) in gongs.chain(platforms.iter().zip(state.platforms.iter())) {
+                match body.get_mut(&mut physic_world) {
+                    Ok(body) => body_state.apply(body),
+                    Err(error) => ::error::log(error),
+                }
+            }
+        }
+
+        // Pickups are only drawn, balls of clients don't take them
+        {
+            let mut sprites = world.write_resource::<::resource::Sprites>();
+            for pickup in &state.pickups {
+                sprites.push(::resource::Sprite {
+                    image: pickup.power_up.image(),
+                    position: ::entity::PICKUP_SPOTS[pickup.spot],
+                    height: ::entity::PICKUP_RADIUS * 2.0,
+                    color: [1.0; 4],
+                });
+            }
+        }
+        ::mode::render(world);
+    }
+}
+
+pub fn run_server(address: &str) {
+    let transport = match Transport::bind(address, None) {
+        Ok(transport) => transport,
+        Err(error) => {
+            ::error::log(error);
+            return;
+        }
+    };
+    let mut world = ::create_world();
+    let mut server = Server::new(transport);
+
+    let mut last_frame_instant = Instant::now();
+    loop {
+        if let Err(error) = server.tick(&mut world) {
+            ::error::log(error);
+        }
+        ::wait_frame(&mut last_frame_instant);
+    }
+}
+
+/// Play on a server, or only watch it if `spectate` is set
+pub fn run_client(server: &str, spectate: bool) {
+    let server = match ::net::resolve(server) {
+        Ok(server) => server,
+        Err(error) => {
+            ::error::log(error);
+            return;
+        }
+    };
+    let bind = ::arg_value("--net-bind").unwrap_or("0.0.0.0:0".into());
+    let mut transport = match Transport::bind(&bind, None) {
+        Ok(transport) => transport,
+        Err(error) => {
+            ::error::log(error);
+            return;
+        }
+    };
+
+    ::std::env::set_var("WINIT_UNIX_BACKEND", "x11");
+    let preferences = ::preferences::Preferences::load();
+    let mut events_loop = ::winit::EventsLoop::new();
+    let mut graphics = ::graphics::Graphics::new(&events_loop, preferences.fullscreen);
+    let mut gilrs = ::gilrs::Gilrs::new().unwrap();
+
+    let mut world = ::create_world();
+    let mut view = ClientView::new();
+    let mut connected = false;
+
+    let mut last_frame_instant = Instant::now();
+    loop {
+        match ::poll_window(&mut events_loop) {
+            Some(ref keys) if !keys.contains(&::winit::VirtualKeyCode::Escape) => (),
+            _ => {
+                let _ = transport.send(&ClientMessage::Disconnect, server);
+                return;
+            }
+        }
+        while let Some(ev) = gilrs.next_event() {
+            gilrs.update(&ev);
+        }
+
+        match transport.receive::<ServerMessage>() {
+            Ok(messages) => {
+                for (message, address) in messages {
+                    if address != server {
+                        continue;
+                    }
+                    match message {
+                        ServerMessage::Welcome { .. } => connected = true,
+                        ServerMessage::Snapshot(delta) => view.receive(&delta),
+                    }
+                }
+            }
+            Err(error) => ::error::log(error),
+        }
+
+        let message = if spectate && connected {
+            ClientMessage::Ack(view.last_frame())
+        } else if spectate {
+            ClientMessage::Spectate
+        } else if connected {
+            let input = gilrs.gamepads()
+                .next()
+                .map(|(_, gamepad)| {
+                    let profile = preferences.profile(gamepad.uuid().as_bytes());
+                    ::state::gamepad_input(gamepad, &profile, [true, true], preferences.aim_config(&profile))
+                })
+                .unwrap_or_default();
+            ClientMessage::Input {
+                input: NetInput::from_input(&input),
+                ack: view.last_frame(),
+            }
+        } else {
+            ClientMessage::Connect
+        };
+        if let Err(error) = transport.send(&message, server) {
+            ::error::log(error);
+        }
+
+        view.update(&mut world);
+        if !connected {
+            world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Wait);
+        }
+        graphics.render(&mut world);
+        ::wait_frame(&mut last_frame_instant);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(x: f32) -> BodyState {
+        BodyState {
+            position: [x, 0.0],
+            angle: 0.0,
+            linear_velocity: [x, 0.0],
+            angular_velocity: 0.0,
+        }
+    }
+
+    fn ball(x: f32) -> BallState {
+        BallState {
+            body: body(x),
+            airjump: ::component::Airjump::default(),
+            radius: 0.1,
+            tint: [1.0; 4],
+            it: false,
+        }
+    }
+
+    fn state(balls: &[(BallId, f32)], gong: f32) -> WorldState {
+        WorldState {
+            balls: balls.iter().map(|&(id, x)| (id, ball(x))).collect(),
+            gongs: vec![body(gong)],
+            ..WorldState::default()
+        }
+    }
+
+    #[test]
+    fn full_delta() {
+        let state = state(&[((1, 1), 0.5), ((2, 1), -0.5)], 0.0);
+        let delta = state.delta(3, None);
+        assert_eq!(delta.base, None);
+        assert_eq!(delta.changed.len(), 2);
+        assert_eq!(WorldState::apply(None, &delta), state);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let base = state(&[((1, 1), 0.5), ((2, 1), -0.5)], 0.0);
+        // Ball 1 moved, ball 2 was deleted and a new ball took its entity id
+        let next = state(&[((1, 1), 0.6), ((2, 2), 0.0)], 0.1);
+        let delta = next.delta(5, Some((4, &base)));
+        assert_eq!(delta.base, Some(4));
+        assert_eq!(delta.changed.iter().map(|&(id, _)| id).collect::<Vec<_>>(), vec![(1, 1), (2, 2)]);
+        assert_eq!(delta.removed, vec![(2, 1)]);
+        assert!(delta.gongs.is_some());
+        assert!(delta.platforms.is_none());
+        assert!(delta.rules.is_none());
+        assert!(delta.timer.is_none());
+        assert_eq!(WorldState::apply(Some(&base), &delta), next);
+    }
+
+    #[test]
+    fn unchanged_delta_is_empty() {
+        let state = state(&[((1, 1), 0.5)], 0.0);
+        let delta = state.delta(2, Some((1, &state)));
+        assert!(delta.changed.is_empty());
+        assert!(delta.removed.is_empty());
+        assert!(delta.gongs.is_none());
+        assert_eq!(WorldState::apply(Some(&state), &delta), state);
+    }
+
+    #[test]
+    fn interpolate_states() {
+        let state = state(&[((1, 1), 0.0), ((2, 1), 1.0)], 0.0);
+        let next = WorldState {
+            balls: vec![((1, 1), ball(1.0))].into_iter().collect(),
+            gongs: vec![body(0.5)],
+            ..WorldState::default()
+        };
+        let between = state.interpolate(&next, 0.5);
+        assert_eq!(between.balls[&(1, 1)].body.position, [0.5, 0.0]);
+        // Balls missing in the next state stay where they are
+        assert_eq!(between.balls[&(2, 1)].body.position, [1.0, 0.0]);
+        assert_eq!(between.gongs[0].position, [0.25, 0.0]);
+    }
+}
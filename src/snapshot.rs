@@ -0,0 +1,228 @@
+use specs;
+use specs::Join;
+use nphysics2d;
+use serde_json;
+use std::fs::File;
+use std::path::Path;
+
+pub const SNAPSHOT_PATH: &str = "snapshot.json";
+
+/// Position and velocity of a rigid body
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BodyState {
+    pub position: [f32; 2],
+    pub angle: f32,
+    pub linear_velocity: [f32; 2],
+    pub angular_velocity: f32,
+}
+
+impl BodyState {
+    pub fn from_body(body: &nphysics2d::object::RigidBody<f32>) -> Self {
+        let position = body.position();
+        let velocity = body.velocity();
+        BodyState {
+            position: [position.translation.vector[0], position.translation.vector[1]],
+            angle: position.rotation.angle(),
+            linear_velocity: [velocity.linear[0], velocity.linear[1]],
+            angular_velocity: velocity.angular,
+        }
+    }
+
+    /// State between `self` (`t` = 0) and `next` (`t` = 1)
+    pub fn interpolate(&self, next: &BodyState, t: f32) -> BodyState {
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        // Rotate by the shortest way
+        let angle_delta = ::wrap_angle(next.angle - self.angle);
+        BodyState {
+            position: [lerp(self.position[0], next.position[0]), lerp(self.position[1], next.position[1])],
+            angle: self.angle + angle_delta * t,
+            linear_velocity: [
+                lerp(self.linear_velocity[0], next.linear_velocity[0]),
+                lerp(self.linear_velocity[1], next.linear_velocity[1]),
+            ],
+            angular_velocity: lerp(self.angular_velocity, next.angular_velocity),
+        }
+    }
+
+    pub fn apply(&self, body: &mut nphysics2d::object::RigidBody<f32>) {
+        body.set_position(::na::Isometry2::new(
+            ::na::Vector2::new(self.position[0], self.position[1]),
+            self.angle,
+        ));
+        body.set_velocity(nphysics2d::math::Velocity::new(
+            ::na::Vector2::new(self.linear_velocity[0], self.linear_velocity[1]),
+            self.angular_velocity,
+        ));
+    }
+}
+
+/// Who controls a ball
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Player {
+    Gamepad {
+        gamepad_id: usize,
+        parts: [bool; 2],
+    },
+    Bot(::component::Difficulty),
+}
+
+/// Who controls a ball and what it keeps from its slot, the rest of its state is in the
+/// `FrameState` of the snapshot
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BallSnapshot {
+    pub player: Player,
+    #[serde(default)]
+    pub handicap: ::component::Handicap,
+    /// Id and color of the slot owning the ball
+    pub slot: Option<(usize, [f32; 4])>,
+}
+
+/// State of the simulation: rules, balls, gongs, moving platforms, pickups and round progress.
+///
+/// Ground, walls and other hazards are created from the rules and are not part of the snapshot.
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub rules: ::rules::Rules,
+    pub balls: Vec<BallSnapshot>,
+    /// State of `balls` in the same order and of the rest of the world
+    pub state: ::net::FrameState,
+}
+
+impl Snapshot {
+    pub fn take(world: &specs::World) -> Self {
+        let mut entities = vec![];
+        let mut balls = vec![];
+        {
+            let inputs = world.read_storage::<::component::Input>();
+            let controls = world.read_storage::<::component::Control>();
+            let bots = world.read_storage::<::component::Bot>();
+            let handicaps = world.read_storage::<::component::Handicap>();
+            let owners = world.read_storage::<::component::Player>();
+            let tints = world.read_storage::<::component::Tint>();
+
+            for (entity, _) in (&*world.entities(), &inputs).join() {
+                let player = if let Some(control) = controls.get(entity) {
+                    Player::Gamepad {
+                        gamepad_id: control.gamepad_id,
+                        parts: control.parts,
+                    }
+                } else if let Some(bot) = bots.get(entity) {
+                    Player::Bot(bot.difficulty)
+                } else {
+                    continue;
+                };
+                entities.push(entity);
+                balls.push(BallSnapshot {
+                    player,
+                    handicap: handicaps.get(entity).cloned().unwrap_or_default(),
+                    slot: owners.get(entity).map(|owner| {
+                        (owner.0, tints.get(entity).map(|tint| tint.0).unwrap_or([1.0; 4]))
+                    }),
+                });
+            }
+        }
+
+        Snapshot {
+            rules: world.read_resource::<::rules::Rules>().clone(),
+            balls,
+            state: ::net::FrameState::take(&entities, world),
+        }
+    }
+
+    /// Replace balls by the ones of the snapshot and put the world in its state.
+    ///
+    /// Gongs and moving platforms are matched in join order, extra ones in the world are left
+    /// untouched.
+    pub fn restore(&self, world: &mut specs::World) {
+        ::entity::set_rules(&self.rules, world);
+        {
+            let inputs = world.read_storage::<::component::Input>();
+            let entities = world.entities();
+            for (_, entity) in (&inputs, &*entities).join() {
+                if let Err(error) = entities.delete(entity) {
+                    ::error::log(error);
+                }
+            }
+        }
+        ::safe_maintain(world);
+
+        let entities = self.balls.iter()
+            .map(|ball| {
+                let entity = match ball.player {
+                    Player::Gamepad { gamepad_id, parts } => ::entity::create_ball(gamepad_id, parts, ball.handicap, world),
+                    Player::Bot(difficulty) => ::entity::create_bot(difficulty, ball.handicap, world),
+                };
+                if let Some((slot, tint)) = ball.slot {
+                    world.write_storage().insert(entity, ::component::Player(slot));
+                    world.write_storage().insert(entity, ::component::Tint(tint));
+                }
+                entity
+            })
+            .collect::<Vec<_>>();
+        self.state.restore(&entities, world);
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ::error::SnapshotError> {
+        let file = File::create(path)?;
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ::error::SnapshotError> {
+        let file = File::open(path)?;
+        Ok(serde_json::from_reader(file)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn body(position: [f32; 2], angle: f32) -> BodyState {
+        BodyState {
+            position,
+            angle,
+            linear_velocity: [0.0, 0.0],
+            angular_velocity: 0.0,
+        }
+    }
+
+    #[test]
+    fn interpolate_position() {
+        let state = body([0.0, 1.0], 0.0).interpolate(&body([1.0, -1.0], 0.0), 0.25);
+        assert_eq!(state.position, [0.25, 0.5]);
+    }
+
+    #[test]
+    fn interpolate_angle_by_the_shortest_way() {
+        let state = body([0.0, 0.0], 3.0).interpolate(&body([0.0, 0.0], -3.0), 0.5);
+        assert!((::wrap_angle(state.angle) - PI).abs() < 1e-4, "angle {}", state.angle);
+    }
+
+    #[test]
+    fn restore_keeps_handicap_and_power_ups() {
+        let handicap = ::component::Handicap {
+            size: 1.5,
+            ..::component::Handicap::default()
+        };
+        let mut world = ::create_world();
+        let bot = ::entity::create_bot(::component::Difficulty::Hard, handicap, &mut world);
+        world.write_storage().insert(bot, ::component::PowerUps(vec![(::power_up::PowerUp::Heavy, 2.0)]));
+        world.write_storage().insert(bot, ::component::Stun(0.5));
+
+        let json = serde_json::to_string(&Snapshot::take(&world)).unwrap();
+        let snapshot: Snapshot = serde_json::from_str(&json).unwrap();
+        let mut restored = ::create_world();
+        snapshot.restore(&mut restored);
+
+        let bots = restored.read_storage::<::component::Bot>();
+        let handicaps = restored.read_storage::<::component::Handicap>();
+        let power_ups = restored.read_storage::<::component::PowerUps>();
+        let stuns = restored.read_storage::<::component::Stun>();
+        let (entity, _) = (&*restored.entities(), &bots).join().next().unwrap();
+        assert_eq!(handicaps.get(entity), Some(&handicap));
+        assert_eq!(power_ups.get(entity).map(|power_ups| power_ups.len()), Some(1));
+        assert!(stuns.get(entity).is_some());
+    }
+}
@@ -1,186 +1,1681 @@
 use gilrs;
 use specs;
 use specs::Join;
-use nphysics2d;
 use entity;
 
+/// Transition requested by the state on top of the stack
+pub enum Trans {
+    None,
+    /// Put a state on top of the current one
+    Push(Box<GameState>),
+    /// Remove the current state, the game quits when the stack is empty
+    Pop,
+    /// Replace the current state
+    Replace(Box<GameState>),
+    Quit,
+}
+
 pub trait GameState {
-    fn update(self: Box<Self>, world: &mut specs::World) -> Box<GameState>;
-    fn event(self: Box<Self>, event: gilrs::ev::Event, world: &mut specs::World)
-        -> Box<GameState>;
-    fn gamepad(
-        self: Box<Self>,
-        id: usize,
-        gamepad: &::gilrs::Gamepad,
-        world: &mut specs::World,
-    ) -> Box<GameState>;
-    fn quit(&self) -> bool {
-        false
+    fn on_enter(&mut self, _world: &mut specs::World) {}
+    fn on_exit(&mut self, _world: &mut specs::World) {}
+    fn update(&mut self, world: &mut specs::World) -> Trans;
+    /// Called on the state on top at the step the round time runs out, before `update`
+    fn time_up(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    /// Draw the state with `DrawImage` and `Sprites` resources
+    fn render(&self, _world: &specs::World) {}
+    fn event(&mut self, _event: gilrs::ev::Event, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn gamepad(&mut self, _id: usize, _gamepad: &::gilrs::Gamepad, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn key(&mut self, _key: ::winit::VirtualKeyCode, _world: &mut specs::World) -> Trans {
+        Trans::None
     }
     fn paused(&self, world: &specs::World) -> bool;
+    /// Whether the state below is rendered before this one
+    fn render_below(&self) -> bool {
+        false
+    }
 }
 
-pub struct NewController {
-    id: usize,
+/// Stack of game states, only the state on top receives inputs
+pub struct StateStack {
+    states: Vec<Box<GameState>>,
+    quit: bool,
 }
 
-impl GameState for NewController {
-    fn update(self: Box<Self>, world: &mut specs::World) -> Box<GameState> {
-        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::NewController);
-        self
+impl StateStack {
+    pub fn new(mut state: Box<GameState>, world: &mut specs::World) -> Self {
+        state.on_enter(world);
+        StateStack {
+            states: vec![state],
+            quit: false,
+        }
     }
-    fn event(self: Box<Self>, event: gilrs::ev::Event, world: &mut specs::World)
-        -> Box<GameState>
-    {
-        if event.id == self.id {
-            match event.event {
-                gilrs::ev::EventType::Disconnected => {
-                    Box::new(Play)
-                },
-                gilrs::ev::EventType::ButtonPressed(gilrs::ev::Button::West, _) => {
-                    entity::create_ball(event.id, [true, true], world);
-                    Box::new(ShowImage::new(::Image::NewController1))
-                },
-                gilrs::ev::EventType::ButtonPressed(gilrs::ev::Button::South, _) => {
-                    entity::create_ball(event.id, [true, false], world);
-                    entity::create_ball(event.id, [false, true], world);
-                    Box::new(ShowImage::new(::Image::NewController2))
-                },
-                gilrs::ev::EventType::ButtonPressed(gilrs::ev::Button::East, _) => {
-                    Box::new(ShowImage::new(::Image::NewControllerSkip))
-                },
-                _ => self
+
+    fn apply(&mut self, trans: Trans, world: &mut specs::World) {
+        match trans {
+            Trans::None => (),
+            Trans::Push(mut state) => {
+                state.on_enter(world);
+                self.states.push(state);
             }
+            Trans::Pop => {
+                if let Some(mut state) = self.states.pop() {
+                    state.on_exit(world);
+                }
+                if self.states.is_empty() {
+                    self.quit = true;
+                }
+            }
+            Trans::Replace(mut state) => {
+                if let Some(mut previous) = self.states.pop() {
+                    previous.on_exit(world);
+                }
+                state.on_enter(world);
+                self.states.push(state);
+            }
+            Trans::Quit => self.quit = true,
+        }
+    }
+
+    pub fn update(&mut self, world: &mut specs::World) {
+        let top = match self.states.len() {
+            0 => return,
+            len => len - 1,
+        };
+        let time_up = ::std::mem::replace(&mut world.write_resource::<::mode::RoundTimer>().time_up, false);
+        let mut trans = Trans::None;
+        if time_up {
+            trans = self.states[top].time_up(world);
+        }
+        if let Trans::None = trans {
+            trans = self.states[top].update(world);
+        }
+        self.apply(trans, world);
+    }
+
+    pub fn render(&self, world: &specs::World) {
+        let top = match self.states.len() {
+            0 => return,
+            len => len - 1,
+        };
+        let mut bottom = top;
+        while bottom > 0 && self.states[bottom].render_below() {
+            bottom -= 1;
+        }
+        for state in &self.states[bottom..] {
+            state.render(world);
+        }
+    }
+
+    pub fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) {
+        let trans = match self.states.last_mut() {
+            Some(state) => state.event(event, world),
+            None => return,
+        };
+        self.apply(trans, world);
+    }
+
+    pub fn gamepad(&mut self, id: usize, gamepad: &::gilrs::Gamepad, world: &mut specs::World) {
+        let trans = match self.states.last_mut() {
+            Some(state) => state.gamepad(id, gamepad, world),
+            None => return,
+        };
+        self.apply(trans, world);
+    }
+
+    pub fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) {
+        let trans = match self.states.last_mut() {
+            Some(state) => state.key(key, world),
+            None => return,
+        };
+        self.apply(trans, world);
+    }
+
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+
+    pub fn paused(&self, world: &specs::World) -> bool {
+        self.states.last().map(|state| state.paused(world)).unwrap_or(true)
+    }
+}
+
+/// Input of a ball controlled by the given parts of the gamepad with its bindings.
+///
+/// Sticks inside the deadzone are ignored and the angle is snapped, smoothing is done by
+/// `ControlSystem` with the `AimFilter` component.
+pub fn gamepad_input(
+    gamepad: &::gilrs::Gamepad,
+    profile: &::binding::Profile,
+    parts: [bool; 2],
+    aim: &::binding::AimConfig,
+) -> ::component::Input {
+    let stick = |axes: [::binding::Axis; 2]| {
+        let v = ::na::Vector2::new(gamepad.value(axes[0].to_gilrs()), gamepad.value(axes[1].to_gilrs()));
+        if v.norm() < aim.deadzone {
+            ::na::zero()
         } else {
-            self
+            v
+        }
+    };
+
+    let mut v = ::na::Vector2::new(0.0, 0.0);
+    let mut jump = false;
+    let mut pressure = None;
+    for part in (0..2).filter(|part| parts[*part]) {
+        v += stick(profile.aim[part]);
+        let button = profile.jump[part].to_gilrs();
+        jump |= gamepad.is_pressed(button);
+        if let Some(data) = gamepad.button_data(button) {
+            pressure = Some(pressure.unwrap_or(0.0f32).max(data.value()));
         }
     }
-    fn gamepad(
-        self: Box<Self>,
-        _id: usize,
-        _gamepad: &::gilrs::Gamepad,
-        _world: &mut specs::World,
-    ) -> Box<GameState>
+
+    ::component::Input {
+        angle: v.try_normalize(0.0001).map(|v| aim.snap_angle(-v[1].atan2(v[0]))),
+        jump,
+        pressure,
+    }
+}
+
+/// Aim of the player of the gamepad: the one tuned for their slot, otherwise the one of their
+/// controller
+fn player_aim(gamepad_id: usize, world: &specs::World) -> ::binding::AimConfig {
+    let slot_aim = world.read_resource::<::resource::Slots>()
+        .gamepad_slot(gamepad_id)
+        .and_then(|slot| slot.aim.clone());
+    slot_aim.unwrap_or_else(|| {
+        let profile = ::binding::profile(gamepad_id, world);
+        world.read_resource::<::preferences::Preferences>().aim_config(&profile).clone()
+    })
+}
+
+/// Number of balls controlled by either a gamepad or a bot
+fn player_count(world: &specs::World) -> usize {
+    world.read_storage::<::component::Input>().join().count()
+}
+
+/// Replace all balls by the ones of the slots, queued slots join the round
+pub fn start_round(world: &mut specs::World) {
     {
-        self
+        let inputs = world.read_storage::<::component::Input>();
+        let pickups = world.read_storage::<::component::Pickup>();
+        let hazards = world.read_storage::<::component::Hazard>();
+        let entities = world.entities();
+        for (_, entity) in (&inputs, &*entities).join() {
+            entities.delete(entity).unwrap();
+        }
+        for (_, entity) in (&pickups, &*entities).join() {
+            entities.delete(entity).unwrap();
+        }
+        for (_, entity) in (&hazards, &*entities).join() {
+            entities.delete(entity).unwrap();
+        }
+    }
+    ::safe_maintain(world);
+    entity::reset_gongs(world);
+    if world.read_resource::<::rules::Rules>().hazards {
+        entity::create_hazards(world);
+    }
+
+    let slots = {
+        let mut slots = world.write_resource::<::resource::Slots>();
+        for slot in &mut slots.slots {
+            slot.queued = false;
+        }
+        slots.slots.clone()
+    };
+    let balls = slots.iter()
+        .flat_map(|slot| entity::create_slot_balls(slot, world))
+        .collect::<Vec<_>>();
+    entity::spread_balls(&balls, world);
+    ::mode::start_round(&balls, world);
+}
+
+/// Draw a translucent bubble around the balls which respawned, it blinks when it is about to end
+fn show_invulnerables(world: &specs::World) {
+    let invulnerables = world.read_storage::<::component::Invulnerable>();
+    let bodies = world.read_storage::<::component::RigidBody>();
+    let physic_world = world.read_resource::<::resource::PhysicWorld>();
+    let mut sprites = world.write_resource::<::resource::Sprites>();
+    for (invulnerable, body) in (&invulnerables, &bodies).join() {
+        if invulnerable.0 < 0.5 && (invulnerable.0 * 10.0) as usize % 2 == 0 {
+            continue;
+        }
+        match body.get(&physic_world) {
+            Ok(body) => {
+                let position = body.position().translation.vector;
+                sprites.push(::resource::Sprite {
+                    image: ::Image::Ball,
+                    position: [position[0], position[1]],
+                    height: 0.16,
+                    color: [1.0, 1.0, 1.0, 0.3],
+                });
+            }
+            Err(error) => ::error::log(error),
+        }
+    }
+}
+
+/// Draw a star above the stunned balls
+fn show_stunned(world: &specs::World) {
+    let stuns = world.read_storage::<::component::Stun>();
+    let bodies = world.read_storage::<::component::RigidBody>();
+    let physic_world = world.read_resource::<::resource::PhysicWorld>();
+    let mut sprites = world.write_resource::<::resource::Sprites>();
+    for (_, body) in (&stuns, &bodies).join() {
+        match body.get(&physic_world) {
+            Ok(body) => {
+                let position = body.position().translation.vector;
+                sprites.push(::resource::Sprite {
+                    image: ::Image::Stun,
+                    position: [position[0], position[1] - 0.1],
+                    height: 0.04,
+                    color: [1.0; 4],
+                });
+            }
+            Err(error) => ::error::log(error),
+        }
+    }
+}
+
+/// Draw the balls of the slots in their color with a label under them
+fn show_slots<F>(world: &specs::World, queued_only: bool, label: F)
+where
+    F: Fn(&::resource::Slot) -> Option<::Image>,
+{
+    let slots = world.read_resource::<::resource::Slots>();
+    let mut sprites = world.write_resource::<::resource::Sprites>();
+    for (i, slot) in slots.slots.iter().enumerate().filter(|(_, slot)| slot.queued || !queued_only) {
+        let x = -0.45 + i as f32 * 0.3;
+        let color = ::resource::PLAYER_COLORS[slot.color];
+        let offsets: &[f32] = if slot.two_balls { &[-0.05, 0.05] } else { &[0.0] };
+        for offset in offsets {
+            sprites.push(::resource::Sprite {
+                image: ::Image::Ball,
+                position: [x + offset, 0.0],
+                height: 0.12,
+                color,
+            });
+        }
+        if let Some(label) = label(slot) {
+            sprites.push(::resource::Sprite {
+                image: label,
+                position: [x, 0.1],
+                height: 0.04,
+                color: [1.0; 4],
+            });
+        }
+    }
+}
+
+fn mode_image(mode: ::rules::Mode) -> ::Image {
+    match mode {
+        ::rules::Mode::Gong => ::Image::ModeGong,
+        ::rules::Mode::KingOfTheHill => ::Image::ModeHill,
+        ::rules::Mode::Tag => ::Image::ModeTag,
+        ::rules::Mode::Survival => ::Image::ModeSurvival,
+        ::rules::Mode::Race => ::Image::ModeRace,
+    }
+}
+
+fn bot_image(difficulty: ::component::Difficulty) -> ::Image {
+    match difficulty {
+        ::component::Difficulty::Easy => ::Image::BotEasy,
+        ::component::Difficulty::Normal => ::Image::BotNormal,
+        ::component::Difficulty::Hard => ::Image::BotHard,
+    }
+}
+
+/// Players join slots, pick their color and get ready
+pub struct Lobby;
+
+impl GameState for Lobby {
+    fn update(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Lobby);
+        world.write_resource::<::resource::Sprites>().push(::resource::Sprite {
+            image: mode_image(world.read_resource::<::rules::Rules>().mode),
+            position: [0.0, -0.161],
+            height: 0.05,
+            color: [1.0; 4],
+        });
+        show_slots(world, false, |slot| match slot.player {
+            ::resource::SlotPlayer::Bot(difficulty) => Some(bot_image(difficulty)),
+            ::resource::SlotPlayer::Gamepad(_) if slot.ready => Some(::Image::Ready),
+            ::resource::SlotPlayer::Gamepad(_) => None,
+        });
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        use resource::SlotPlayer;
+
+        let button = match event.event {
+            gilrs::ev::EventType::ButtonPressed(button, _) => button,
+            _ => return Trans::None,
+        };
+
+        let action = ::binding::action(&event, world);
+        let bot_difficulty = world.read_resource::<::preferences::Preferences>().bot_difficulty;
+        let command = {
+            let mut slots = world.write_resource::<::resource::Slots>();
+            if slots.gamepad_slot_mut(event.id).is_none() {
+                slots.join(SlotPlayer::Gamepad(event.id), false);
+                return Trans::None;
+            }
+            lobby_button(&mut slots, event.id, button, action, bot_difficulty)
+        };
+        match command {
+            LobbyCommand::None => Trans::None,
+            LobbyCommand::Start => {
+                for slot in &mut world.write_resource::<::resource::Slots>().slots {
+                    slot.score = 0;
+                }
+                start_round(world);
+                Trans::Replace(Box::new(Play))
+            }
+            LobbyCommand::Menu => Trans::Push(Box::new(Pause::new(false))),
+        }
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        if ::binding::menu_key(key, world) == Some(::binding::MenuKey::Back) {
+            Trans::Push(Box::new(Pause::new(false)))
+        } else {
+            Trans::None
+        }
     }
     fn paused(&self, _world: &specs::World) -> bool {
         true
     }
 }
 
-pub struct ShowImage {
+/// What the lobby must do after a button
+enum LobbyCommand {
+    None,
+    Start,
+    /// Open the pause menu to change rules and settings
+    Menu,
+}
+
+/// Apply the button pressed by the gamepad of a slot and its bound action
+///
+/// Start begins the round if everyone is ready and opens the menu otherwise.
+fn lobby_button(
+    slots: &mut ::resource::Slots,
+    gamepad_id: usize,
+    button: gilrs::ev::Button,
+    action: Option<::binding::Action>,
+    bot_difficulty: ::component::Difficulty,
+) -> LobbyCommand {
+    use binding::Action;
+    use gilrs::ev::Button;
+    use resource::SlotPlayer;
+
+    let last_bot = slots.slots.iter()
+        .rposition(|slot| if let SlotPlayer::Bot(_) = slot.player { true } else { false });
+
+    match action {
+        Some(Action::Confirm) => {
+            let slot = slots.gamepad_slot_mut(gamepad_id).unwrap();
+            slot.ready = !slot.ready;
+        }
+        Some(Action::Back) => slots.remove_gamepad(gamepad_id),
+        Some(Action::Option) => {
+            let slot = slots.gamepad_slot_mut(gamepad_id).unwrap();
+            slot.two_balls = !slot.two_balls;
+        }
+        Some(Action::AddBot) => {
+            if let Some(slot) = slots.join(SlotPlayer::Bot(bot_difficulty), false) {
+                slot.ready = true;
+            }
+        }
+        Some(Action::RemoveBot) => {
+            if let Some(i) = last_bot {
+                slots.slots.remove(i);
+            }
+        }
+        Some(Action::Start) if slots.all_ready() && slots.ball_count() >= 2 => return LobbyCommand::Start,
+        Some(Action::Start) => return LobbyCommand::Menu,
+        None => lobby_dpad(slots, gamepad_id, button, last_bot),
+    }
+    LobbyCommand::None
+}
+
+/// Change the color of the slot or the difficulty of the last bot
+fn lobby_dpad(
+    slots: &mut ::resource::Slots,
+    gamepad_id: usize,
+    button: gilrs::ev::Button,
+    last_bot: Option<usize>,
+) {
+    use gilrs::ev::Button;
+    use resource::SlotPlayer;
+
+    match button {
+        Button::DPadLeft | Button::DPadRight => {
+            let count = ::resource::PLAYER_COLORS.len();
+            let slot = slots.gamepad_slot_mut(gamepad_id).unwrap();
+            slot.color = if button == Button::DPadRight {
+                (slot.color + 1) % count
+            } else {
+                (slot.color + count - 1) % count
+            };
+        }
+        Button::DPadUp | Button::DPadDown => {
+            if let Some(slot) = last_bot.map(|i| &mut slots.slots[i]) {
+                if let SlotPlayer::Bot(ref mut difficulty) = slot.player {
+                    *difficulty = difficulty.step(button == Button::DPadUp);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Score the winners of the round and show them over the arena, then start the next round
+pub struct RoundOver {
     time: usize,
-    image: ::Image,
+    /// Slot ids of the winners
+    winners: Vec<usize>,
 }
 
-impl ShowImage {
-    fn new(image: ::Image) -> Self {
-        ShowImage {
-            image,
-            time: 30,
+impl RoundOver {
+    fn new(winners: Vec<usize>) -> Self {
+        RoundOver {
+            time: 150,
+            winners,
         }
     }
 }
 
-impl GameState for ShowImage {
-    fn update(mut self: Box<Self>, world: &mut specs::World) -> Box<GameState> {
-        world.write_resource::<::resource::DrawImage>().0 = Some(self.image);
-        self.time -= 1;
+impl GameState for RoundOver {
+    fn on_enter(&mut self, world: &mut specs::World) {
+        for slot in &mut world.write_resource::<::resource::Slots>().slots {
+            if self.winners.contains(&slot.id) {
+                slot.score += 1;
+            }
+        }
+    }
+    fn update(&mut self, world: &mut specs::World) -> Trans {
+        self.time = self.time.saturating_sub(1);
         if self.time == 0 {
-            Box::new(Play)
+            start_round(world);
+            Trans::Pop
         } else {
-            self
+            Trans::None
         }
     }
-    fn event(self: Box<Self>, _event: gilrs::ev::Event, _world: &mut specs::World)
-        -> Box<GameState>
-    {
-        self
-    }
-    fn gamepad(
-        self: Box<Self>,
-        _id: usize,
-        _gamepad: &::gilrs::Gamepad,
-        _world: &mut specs::World,
-    ) -> Box<GameState>
-    {
-        self
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::RoundOver);
+        show_slots(world, false, |slot| if self.winners.contains(&slot.id) { Some(::Image::Winner) } else { None });
+
+        // Scores as small balls under the slots
+        let slots = world.read_resource::<::resource::Slots>();
+        let mut sprites = world.write_resource::<::resource::Sprites>();
+        for (i, slot) in slots.slots.iter().enumerate() {
+            for j in 0..slot.score {
+                sprites.push(::resource::Sprite {
+                    image: ::Image::Ball,
+                    position: [-0.55 + i as f32 * 0.3 + j as f32 * 0.03, 0.17],
+                    height: 0.025,
+                    color: ::resource::PLAYER_COLORS[slot.color],
+                });
+            }
+        }
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        if let gilrs::ev::EventType::ButtonPressed(..) = event.event {
+            if ::binding::action(&event, world) == Some(::binding::Action::Start) {
+                return Trans::Push(Box::new(Pause::new(false)));
+            }
+        }
+        Trans::None
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        if ::binding::menu_key(key, world) == Some(::binding::MenuKey::Back) {
+            Trans::Push(Box::new(Pause::new(false)))
+        } else {
+            Trans::None
+        }
     }
     fn paused(&self, _world: &specs::World) -> bool {
         true
     }
+    fn render_below(&self) -> bool {
+        true
+    }
 }
 
 pub struct Play;
 
 impl GameState for Play {
-    fn update(self: Box<Self>, world: &mut specs::World) -> Box<GameState> {
-        let controls = world.read_storage::<::component::Control>();
-        let count = controls.join().count();
-        if count == 0 {
-            world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Start);
-        } else if count == 1 {
+    fn update(&mut self, world: &mut specs::World) -> Trans {
+        if !world.read_resource::<::resource::Slots>().any_disconnected() {
+            if let Some(winners) = ::mode::round_over(world) {
+                return Trans::Push(Box::new(RoundOver::new(winners)));
+            }
+        }
+        if player_count(world) == 0 {
+            Trans::Replace(Box::new(Lobby))
+        } else {
+            Trans::None
+        }
+    }
+    fn time_up(&mut self, world: &mut specs::World) -> Trans {
+        match ::mode::time_up(world) {
+            Some(winners) => Trans::Push(Box::new(RoundOver::new(winners))),
+            None => {
+                world.write_resource::<::mode::RoundTimer>().sudden_death = true;
+                Trans::None
+            }
+        }
+    }
+    fn render(&self, world: &specs::World) {
+        if world.read_resource::<::resource::Slots>().any_disconnected() {
+            world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Disconnected);
+        } else if player_count(world) == 1 {
             world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Wait);
         }
-        self
+        ::mode::render(world);
+        show_invulnerables(world);
+        show_stunned(world);
+        show_slots(world, true, |_| Some(::Image::NextRound));
     }
-    fn event(self: Box<Self>, event: gilrs::ev::Event, world: &mut specs::World)
-        -> Box<GameState>
-    {
-        let controls = world.read_storage::<::component::Control>();
-        if controls.join().filter(|c| c.gamepad_id == event.id).next().is_none() {
-            return Box::new(NewController { id: event.id })
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        if let gilrs::ev::EventType::ButtonPressed(..) = event.event {
+            let action = ::binding::action(&event, world);
+            let mut slots = world.write_resource::<::resource::Slots>();
+            if slots.gamepad_slot_mut(event.id).is_none() {
+                // New players join at the next round
+                slots.join(::resource::SlotPlayer::Gamepad(event.id), true);
+            } else if action == Some(::binding::Action::Start) {
+                return Trans::Push(Box::new(Pause::new(true)));
+            }
         }
-        self
+        Trans::None
     }
-    fn gamepad(
-        self: Box<Self>,
-        id: usize,
-        gamepad: &::gilrs::Gamepad,
-        world: &mut specs::World,
-    ) -> Box<GameState>
-    {
+    fn gamepad(&mut self, id: usize, gamepad: &::gilrs::Gamepad, world: &mut specs::World) -> Trans {
+        let profile = ::binding::profile(id, world);
+        let aim = player_aim(id, world);
+        let entities = world.entities();
         let controls = world.read_storage::<::component::Control>();
+        let handicaps = world.read_storage::<::component::Handicap>();
+        let gongs = world.read_storage::<::component::Gong>();
         let bodies = world.read_storage::<::component::RigidBody>();
-        let mut airjumps = world.write_storage::<::component::Airjump>();
-        let mut physic_world = world.write_resource::<::resource::PhysicWorld>();
-
-        for (c, airjump, body) in (&controls, &mut airjumps, &bodies).join().filter(|(c, _, _)| c.gamepad_id == id) {
-            let body = body.get_mut(&mut physic_world);
-            // Set angle
-            let mut v = ::na::Vector2::new(0.0, 0.0);
-            if c.parts[0] {
-                v[0] += gamepad.value(gilrs::ev::Axis::LeftStickX);
-                v[1] += gamepad.value(gilrs::ev::Axis::LeftStickY);
-            }
-            if c.parts[1] {
-                v[0] += gamepad.value(gilrs::ev::Axis::RightStickX);
-                v[1] += gamepad.value(gilrs::ev::Axis::RightStickY);
-            }
-            if let Some(v) = v.try_normalize(0.0001) {
-                let current_angle = body.position().rotation.angle();
-                let next_angle = -v[1].atan2(v[0]);
-                body.apply_displacement(&nphysics2d::math::Velocity::angular(next_angle - current_angle));
-            }
-
-            // Jump
-            // IDEA: add to velocity instead of reset it
-            if (gamepad.is_pressed(gilrs::ev::Button::LeftTrigger) && c.parts[0])
-                || (gamepad.is_pressed(gilrs::ev::Button::RightTrigger) && c.parts[1])
-            {
-                if airjump.0 {
-                    airjump.0 = false;
-                    let angle = body.position().rotation.angle();
-                    body.set_velocity(nphysics2d::math::Velocity::linear(angle.cos()*entity::BALL_VELOCITY, angle.sin()*entity::BALL_VELOCITY));
+        let physic_world = world.read_resource::<::resource::PhysicWorld>();
+        let mut inputs = world.write_storage::<::component::Input>();
+        let mut filters = world.write_storage::<::component::AimFilter>();
+
+        let gong_positions = (&gongs, &bodies).join()
+            .filter_map(|(_, body)| body.get(&physic_world).ok())
+            .map(|body| body.position().translation.vector)
+            .collect::<Vec<_>>();
+
+        for (entity, c, input) in (&*entities, &controls, &mut inputs).join().filter(|(_, c, _)| c.gamepad_id == id) {
+            *input = gamepad_input(gamepad, &profile, c.parts, &aim);
+            filters.insert(entity, aim.filter());
+
+            // Aim assist of the handicap bends the aim toward the nearest gong
+            let assist = handicaps.get(entity).map(|handicap| handicap.aim_assist).unwrap_or(0.0);
+            // Balls without body yet keep their raw aim
+            if let (Some(angle), true, Some(body)) = (input.angle, assist > 0.0, bodies.get(entity)) {
+                match body.get(&physic_world) {
+                    Ok(body) => {
+                        let position = body.position().translation.vector;
+                        input.angle = Some(::handicap::assist_aim(angle, position, &gong_positions, assist));
+                    }
+                    Err(error) => ::error::log(error),
                 }
             }
         }
-        self
+        Trans::None
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        if ::binding::menu_key(key, world) == Some(::binding::MenuKey::Back) {
+            Trans::Push(Box::new(Pause::new(true)))
+        } else {
+            Trans::None
+        }
     }
     fn paused(&self, world: &specs::World) -> bool {
-        let controls = world.read_storage::<::component::Control>();
-        let count = controls.join().count();
-        count < 2
+        player_count(world) < 2 || world.read_resource::<::resource::Slots>().any_disconnected()
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum PauseItem {
+    Resume,
+    Restart,
+    Rules,
+    Handicap,
+    Settings,
+    Quit,
+}
+
+const PAUSE_ITEMS: [PauseItem; 6] = [
+    PauseItem::Resume,
+    PauseItem::Restart,
+    PauseItem::Rules,
+    PauseItem::Handicap,
+    PauseItem::Settings,
+    PauseItem::Quit,
+];
+
+/// Vertical position of the first item of the pause image and the space between items
+const PAUSE_FIRST_ITEM_Y: f32 = -0.223;
+const PAUSE_ITEM_SPACING: f32 = 0.100;
+
+/// Draw a ball in front of the selected item of a menu image
+fn show_cursor(world: &specs::World, first_item_y: f32, item_spacing: f32, selected: usize) {
+    world.write_resource::<::resource::Sprites>().push(::resource::Sprite {
+        image: ::Image::Ball,
+        position: [-0.45, first_item_y + selected as f32 * item_spacing],
+        height: 0.06,
+        color: [1.0; 4],
+    });
+}
+
+/// Menu drawn as an image with one item per line: up and down select an item, left, right and
+/// confirm change it and back leaves the menu
+trait Menu {
+    /// Number of items
+    fn items(&self) -> usize;
+    fn selected_mut(&mut self) -> &mut usize;
+    /// Change the selected item backward or forward, confirm changes it forward, `gamepad_id` is
+    /// the gamepad which changed it or `None` for the keyboard
+    fn confirm(&mut self, forward: bool, gamepad_id: Option<usize>, world: &mut specs::World) -> Trans;
+    /// Whether left and right change the items
+    fn sideways(&self) -> bool {
+        true
+    }
+
+    fn select(&mut self, forward: bool) -> Trans {
+        let count = self.items();
+        let selected = self.selected_mut();
+        *selected = if forward {
+            (*selected + 1) % count
+        } else {
+            (*selected + count - 1) % count
+        };
+        Trans::None
+    }
+
+    fn menu_event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        let sideways = self.sideways();
+        match event.event {
+            gilrs::ev::EventType::ButtonPressed(gilrs::ev::Button::DPadUp, _) => return self.select(false),
+            gilrs::ev::EventType::ButtonPressed(gilrs::ev::Button::DPadDown, _) => return self.select(true),
+            gilrs::ev::EventType::ButtonPressed(gilrs::ev::Button::DPadLeft, _) if sideways => {
+                return self.confirm(false, Some(event.id), world)
+            }
+            gilrs::ev::EventType::ButtonPressed(gilrs::ev::Button::DPadRight, _) if sideways => {
+                return self.confirm(true, Some(event.id), world)
+            }
+            _ => (),
+        }
+        match ::binding::action(&event, world) {
+            Some(::binding::Action::Confirm) => self.confirm(true, Some(event.id), world),
+            Some(::binding::Action::Back) => Trans::Pop,
+            _ => Trans::None,
+        }
+    }
+
+    fn menu_key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        match ::binding::menu_key(key, world) {
+            Some(::binding::MenuKey::Up) => self.select(false),
+            Some(::binding::MenuKey::Down) => self.select(true),
+            Some(::binding::MenuKey::Left) if self.sideways() => self.confirm(false, None, world),
+            Some(::binding::MenuKey::Right) if self.sideways() => self.confirm(true, None, world),
+            Some(::binding::MenuKey::Confirm) => self.confirm(true, None, world),
+            Some(::binding::MenuKey::Back) => Trans::Pop,
+            _ => Trans::None,
+        }
+    }
+}
+
+/// Freeze the game, pushed on top of the state to resume
+pub struct Pause {
+    /// Whether a round is being played and can be restarted, rules and handicaps can only be changed
+    /// outside rounds
+    in_round: bool,
+    selected: usize,
+}
+
+impl Pause {
+    pub fn new(in_round: bool) -> Self {
+        Pause {
+            in_round,
+            selected: 0,
+        }
+    }
+}
+
+impl Menu for Pause {
+    fn items(&self) -> usize {
+        PAUSE_ITEMS.len()
+    }
+    fn selected_mut(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+    /// Open the selected item, `gamepad_id` is the gamepad whose slot the handicap and settings
+    /// items edit
+    fn confirm(&mut self, _forward: bool, gamepad_id: Option<usize>, world: &mut specs::World) -> Trans {
+        match PAUSE_ITEMS[self.selected] {
+            PauseItem::Resume => Trans::Pop,
+            PauseItem::Restart if self.in_round => {
+                start_round(world);
+                Trans::Pop
+            }
+            PauseItem::Restart => Trans::None,
+            PauseItem::Rules if !self.in_round => Trans::Push(Box::new(RulesMenu::new())),
+            PauseItem::Rules => Trans::None,
+            PauseItem::Handicap if !self.in_round => {
+                let slots = world.read_resource::<::resource::Slots>();
+                match gamepad_id {
+                    Some(id) if slots.gamepad_slot(id).is_some() => Trans::Push(Box::new(HandicapMenu::new(id))),
+                    _ => Trans::None,
+                }
+            }
+            PauseItem::Handicap => Trans::None,
+            PauseItem::Settings => Trans::Push(Box::new(Settings::new(gamepad_id, world))),
+            PauseItem::Quit => Trans::Quit,
+        }
+    }
+    fn sideways(&self) -> bool {
+        false
+    }
+}
+
+impl GameState for Pause {
+    fn update(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Pause);
+        show_cursor(world, PAUSE_FIRST_ITEM_Y, PAUSE_ITEM_SPACING, self.selected);
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        // Start resumes like it paused
+        match ::binding::action(&event, world) {
+            Some(::binding::Action::Start) => Trans::Pop,
+            _ => self.menu_event(event, world),
+        }
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        self.menu_key(key, world)
+    }
+    fn paused(&self, _world: &specs::World) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RulesItem {
+    Mode,
+    JumpStrength,
+    JumpVelocity,
+    Airjumps,
+    AirjumpRegen,
+    RestoredBy,
+    PowerUps,
+    Hazards,
+    Combat,
+    RoundTime,
+    SuddenDeath,
+    Gong,
+    Back,
+}
+
+const RULES_ITEMS: [RulesItem; 13] = [
+    RulesItem::Mode,
+    RulesItem::JumpStrength,
+    RulesItem::JumpVelocity,
+    RulesItem::Airjumps,
+    RulesItem::AirjumpRegen,
+    RulesItem::RestoredBy,
+    RulesItem::PowerUps,
+    RulesItem::Hazards,
+    RulesItem::Combat,
+    RulesItem::RoundTime,
+    RulesItem::SuddenDeath,
+    RulesItem::Gong,
+    RulesItem::Back,
+];
+
+/// Vertical position of the first item of the rules image and the space between items
+const RULES_FIRST_ITEM_Y: f32 = -0.318;
+const RULES_ITEM_SPACING: f32 = 0.057;
+
+/// Edit the rules resource from the lobby, rules are saved in preferences when leaving
+pub struct RulesMenu {
+    selected: usize,
+}
+
+impl RulesMenu {
+    pub fn new() -> Self {
+        RulesMenu {
+            selected: 0,
+        }
+    }
+}
+
+impl Menu for RulesMenu {
+    fn items(&self) -> usize {
+        RULES_ITEMS.len()
+    }
+    fn selected_mut(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+    fn confirm(&mut self, forward: bool, _gamepad_id: Option<usize>, world: &mut specs::World) -> Trans {
+        let mut rules = world.write_resource::<::rules::Rules>();
+        match RULES_ITEMS[self.selected] {
+            RulesItem::Mode => rules.mode = rules.mode.next(forward),
+            RulesItem::JumpStrength => rules.jump_strength = rules.jump_strength.next(forward),
+            RulesItem::JumpVelocity => rules.jump_velocity = rules.jump_velocity.next(),
+            RulesItem::Airjumps => {
+                rules.max_airjumps = if forward {
+                    (rules.max_airjumps + 1).min(::rules::MAX_AIRJUMPS)
+                } else {
+                    rules.max_airjumps.saturating_sub(1).max(1)
+                };
+            }
+            RulesItem::AirjumpRegen => {
+                let count = ::rules::AIRJUMP_REGENS.len();
+                let index = ::rules::AIRJUMP_REGENS.iter().position(|regen| *regen == rules.airjump_regen).unwrap_or(0);
+                let index = if forward { (index + 1) % count } else { (index + count - 1) % count };
+                rules.airjump_regen = ::rules::AIRJUMP_REGENS[index];
+            }
+            RulesItem::RestoredBy => rules.ground_restore_only = !rules.ground_restore_only,
+            RulesItem::PowerUps => rules.power_ups = !rules.power_ups,
+            RulesItem::Hazards => rules.hazards = !rules.hazards,
+            RulesItem::Combat => rules.combat = !rules.combat,
+            RulesItem::RoundTime => {
+                let count = ::rules::ROUND_TIMES.len();
+                let index = ::rules::ROUND_TIMES.iter().position(|time| *time == rules.round_time).unwrap_or(0);
+                let index = if forward { (index + 1) % count } else { (index + count - 1) % count };
+                rules.round_time = ::rules::ROUND_TIMES[index];
+            }
+            RulesItem::SuddenDeath => rules.sudden_death = !rules.sudden_death,
+            RulesItem::Gong => return Trans::Push(Box::new(GongMenu::new())),
+            RulesItem::Back => return Trans::Pop,
+        }
+        Trans::None
+    }
+}
+
+impl GameState for RulesMenu {
+    fn on_exit(&mut self, world: &mut specs::World) {
+        let rules = world.read_resource::<::rules::Rules>().clone();
+        let mut preferences = world.write_resource::<::preferences::Preferences>();
+        preferences.rules = rules;
+        if let Err(error) = preferences.save() {
+            ::error::log(error);
+        }
+    }
+    fn update(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Rules);
+        show_cursor(world, RULES_FIRST_ITEM_Y, RULES_ITEM_SPACING, self.selected);
+
+        let rules = world.read_resource::<::rules::Rules>();
+        let mut sprites = world.write_resource::<::resource::Sprites>();
+        let y = |item: usize| RULES_FIRST_ITEM_Y + item as f32 * RULES_ITEM_SPACING;
+        sprites.push(::resource::Sprite {
+            image: mode_image(rules.mode),
+            position: [MENU_VALUE_X, y(0)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: match rules.jump_strength {
+                ::rules::JumpStrength::Fixed => ::Image::JumpFixed,
+                ::rules::JumpStrength::Analog => ::Image::JumpAnalog,
+                ::rules::JumpStrength::Charged => ::Image::JumpCharged,
+            },
+            position: [MENU_VALUE_X, y(1)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: match rules.jump_velocity {
+                ::rules::JumpVelocity::Reset => ::Image::VelocityReset,
+                ::rules::JumpVelocity::Add => ::Image::VelocityAdd,
+            },
+            position: [MENU_VALUE_X, y(2)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        for i in 0..rules.max_airjumps {
+            sprites.push(::resource::Sprite {
+                image: ::Image::Ball,
+                position: [MENU_VALUE_X - 0.1 + i as f32 * 0.035, y(3)],
+                height: 0.03,
+                color: [1.0; 4],
+            });
+        }
+        sprites.push(::resource::Sprite {
+            image: match rules.airjump_regen {
+                Some(time) if time <= 1.0 => ::Image::Regen1,
+                Some(time) if time <= 2.0 => ::Image::Regen2,
+                Some(_) => ::Image::Regen4,
+                None => ::Image::Off,
+            },
+            position: [MENU_VALUE_X, y(4)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: if rules.ground_restore_only { ::Image::RestoreGround } else { ::Image::RestoreAny },
+            position: [MENU_VALUE_X, y(5)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: if rules.power_ups { ::Image::On } else { ::Image::Off },
+            position: [MENU_VALUE_X, y(6)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: if rules.hazards { ::Image::On } else { ::Image::Off },
+            position: [MENU_VALUE_X, y(7)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: if rules.combat { ::Image::On } else { ::Image::Off },
+            position: [MENU_VALUE_X, y(8)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        match rules.round_time {
+            Some(time) => sprites.push_number(time as usize, [MENU_VALUE_X, y(9)], 0.04),
+            None => sprites.push(::resource::Sprite {
+                image: ::Image::Off,
+                position: [MENU_VALUE_X, y(9)],
+                height: 0.04,
+                color: [1.0; 4],
+            }),
+        }
+        sprites.push(::resource::Sprite {
+            image: if rules.sudden_death { ::Image::On } else { ::Image::Off },
+            position: [MENU_VALUE_X, y(10)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        self.menu_event(event, world)
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        self.menu_key(key, world)
+    }
+    fn paused(&self, _world: &specs::World) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum GongItem {
+    Count,
+    Mount,
+    Hits,
+    Impact,
+    Back,
+}
+
+const GONG_ITEMS: [GongItem; 5] = [
+    GongItem::Count,
+    GongItem::Mount,
+    GongItem::Hits,
+    GongItem::Impact,
+    GongItem::Back,
+];
+
+/// Vertical position of the first item of the gong image and the space between items
+const GONG_FIRST_ITEM_Y: f32 = -0.192;
+const GONG_ITEM_SPACING: f32 = 0.108;
+
+/// Edit the gong rules from the rules menu, which saves them when leaving
+pub struct GongMenu {
+    selected: usize,
+}
+
+impl GongMenu {
+    pub fn new() -> Self {
+        GongMenu {
+            selected: 0,
+        }
+    }
+}
+
+impl Menu for GongMenu {
+    fn items(&self) -> usize {
+        GONG_ITEMS.len()
+    }
+    fn selected_mut(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+    fn confirm(&mut self, forward: bool, _gamepad_id: Option<usize>, world: &mut specs::World) -> Trans {
+        let mut rules = world.write_resource::<::rules::Rules>();
+        match GONG_ITEMS[self.selected] {
+            GongItem::Count => {
+                rules.gong.count = if forward {
+                    (rules.gong.count + 1).min(::rules::MAX_GONGS)
+                } else {
+                    rules.gong.count.saturating_sub(1).max(1)
+                };
+            }
+            GongItem::Mount => rules.gong.mount = rules.gong.mount.next(forward),
+            GongItem::Hits => {
+                rules.gong.hits = if forward {
+                    (rules.gong.hits + 1).min(::rules::MAX_GONG_HITS)
+                } else {
+                    rules.gong.hits.saturating_sub(1).max(1)
+                };
+            }
+            GongItem::Impact => {
+                let count = ::rules::GONG_SPEEDS.len();
+                let index = ::rules::GONG_SPEEDS.iter().position(|speed| *speed == rules.gong.min_speed).unwrap_or(0);
+                let index = if forward { (index + 1) % count } else { (index + count - 1) % count };
+                rules.gong.min_speed = ::rules::GONG_SPEEDS[index];
+            }
+            GongItem::Back => return Trans::Pop,
+        }
+        Trans::None
+    }
+}
+
+impl GameState for GongMenu {
+    fn update(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::GongRules);
+        show_cursor(world, GONG_FIRST_ITEM_Y, GONG_ITEM_SPACING, self.selected);
+
+        let rules = world.read_resource::<::rules::Rules>();
+        let mut sprites = world.write_resource::<::resource::Sprites>();
+        let y = |item: usize| GONG_FIRST_ITEM_Y + item as f32 * GONG_ITEM_SPACING;
+        // Gongs and hits are shown as gauges of small balls
+        for &(count, item) in &[(rules.gong.count, 0), (rules.gong.hits, 2)] {
+            for i in 0..count {
+                sprites.push(::resource::Sprite {
+                    image: ::Image::Ball,
+                    position: [MENU_VALUE_X - 0.1 + i as f32 * 0.035, y(item)],
+                    height: 0.03,
+                    color: [1.0; 4],
+                });
+            }
+        }
+        sprites.push(::resource::Sprite {
+            image: match rules.gong.mount {
+                ::rules::GongMount::Spring => ::Image::MountSpring,
+                ::rules::GongMount::Pendulum => ::Image::MountPendulum,
+                ::rules::GongMount::Rail => ::Image::MountRail,
+                ::rules::GongMount::Moving => ::Image::MountMoving,
+            },
+            position: [MENU_VALUE_X, y(1)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: match rules.gong.min_speed {
+                Some(speed) if speed <= ::rules::GONG_SPEEDS[1].unwrap_or(0.0) => ::Image::ImpactMedium,
+                Some(_) => ::Image::ImpactHard,
+                None => ::Image::ImpactAny,
+            },
+            position: [MENU_VALUE_X, y(3)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        self.menu_event(event, world)
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        self.menu_key(key, world)
+    }
+    fn paused(&self, _world: &specs::World) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum HandicapItem {
+    Size,
+    Jump,
+    Airjumps,
+    AimAssist,
+    Back,
+}
+
+const HANDICAP_ITEMS: [HandicapItem; 5] = [
+    HandicapItem::Size,
+    HandicapItem::Jump,
+    HandicapItem::Airjumps,
+    HandicapItem::AimAssist,
+    HandicapItem::Back,
+];
+
+/// Vertical position of the first item of the handicap image and the space between items
+const HANDICAP_FIRST_ITEM_Y: f32 = -0.192;
+const HANDICAP_ITEM_SPACING: f32 = 0.108;
+
+/// Edit the handicap of the slot of a gamepad from the lobby, its next balls get it
+pub struct HandicapMenu {
+    gamepad_id: usize,
+    selected: usize,
+}
+
+impl HandicapMenu {
+    pub fn new(gamepad_id: usize) -> Self {
+        HandicapMenu {
+            gamepad_id,
+            selected: 0,
+        }
+    }
+}
+
+impl Menu for HandicapMenu {
+    fn items(&self) -> usize {
+        HANDICAP_ITEMS.len()
+    }
+    fn selected_mut(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+    fn confirm(&mut self, forward: bool, _gamepad_id: Option<usize>, world: &mut specs::World) -> Trans {
+        if HANDICAP_ITEMS[self.selected] == HandicapItem::Back {
+            return Trans::Pop;
+        }
+
+        let mut slots = world.write_resource::<::resource::Slots>();
+        let handicap = match slots.gamepad_slot_mut(self.gamepad_id) {
+            Some(slot) => &mut slot.handicap,
+            // The slot left meanwhile
+            None => return Trans::Pop,
+        };
+        let step = |choices: &[f32], value: f32| {
+            let count = choices.len();
+            let index = choices.iter().position(|choice| *choice == value).unwrap_or(0);
+            let index = if forward { (index + 1) % count } else { (index + count - 1) % count };
+            choices[index]
+        };
+        match HANDICAP_ITEMS[self.selected] {
+            HandicapItem::Size => handicap.size = step(&::handicap::SIZES, handicap.size),
+            HandicapItem::Jump => handicap.jump = step(&::handicap::JUMPS, handicap.jump),
+            HandicapItem::Airjumps => {
+                handicap.extra_airjumps = if forward {
+                    (handicap.extra_airjumps + 1).min(::handicap::MAX_EXTRA_AIRJUMPS)
+                } else {
+                    handicap.extra_airjumps.saturating_sub(1)
+                };
+            }
+            HandicapItem::AimAssist => handicap.aim_assist = step(&::handicap::AIM_ASSISTS, handicap.aim_assist),
+            HandicapItem::Back => (),
+        }
+        Trans::None
+    }
+}
+
+impl GameState for HandicapMenu {
+    fn update(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Handicap);
+        show_cursor(world, HANDICAP_FIRST_ITEM_Y, HANDICAP_ITEM_SPACING, self.selected);
+
+        let slots = world.read_resource::<::resource::Slots>();
+        let slot = match slots.gamepad_slot(self.gamepad_id) {
+            Some(slot) => slot,
+            None => return,
+        };
+        let handicap = slot.handicap;
+        let mut sprites = world.write_resource::<::resource::Sprites>();
+        let y = |item: usize| HANDICAP_FIRST_ITEM_Y + item as f32 * HANDICAP_ITEM_SPACING;
+        // A ball of the slot color beside the title tells whose handicap it is
+        sprites.push(::resource::Sprite {
+            image: ::Image::Ball,
+            position: [-0.6, -0.377],
+            height: 0.1,
+            color: ::resource::PLAYER_COLORS[slot.color],
+        });
+        // Size, jump velocity and aim assist are shown in percent
+        sprites.push_number((handicap.size * 100.0).round() as usize, [MENU_VALUE_X, y(0)], 0.04);
+        sprites.push_number((handicap.jump * 100.0).round() as usize, [MENU_VALUE_X, y(1)], 0.04);
+        for i in 0..handicap.extra_airjumps {
+            sprites.push(::resource::Sprite {
+                image: ::Image::Ball,
+                position: [MENU_VALUE_X - 0.1 + i as f32 * 0.035, y(2)],
+                height: 0.03,
+                color: [1.0; 4],
+            });
+        }
+        if handicap.aim_assist > 0.0 {
+            sprites.push_number((handicap.aim_assist * 100.0).round() as usize, [MENU_VALUE_X, y(3)], 0.04);
+        } else {
+            sprites.push(::resource::Sprite {
+                image: ::Image::Off,
+                position: [MENU_VALUE_X, y(3)],
+                height: 0.04,
+                color: [1.0; 4],
+            });
+        }
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        self.menu_event(event, world)
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        self.menu_key(key, world)
+    }
+    fn paused(&self, _world: &specs::World) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum SettingsItem {
+    Fullscreen,
+    Deadzone,
+    Smoothing,
+    TurnRate,
+    Snapping,
+    BotLevel,
+    Controls,
+    Back,
+}
+
+const SETTINGS_ITEMS: [SettingsItem; 8] = [
+    SettingsItem::Fullscreen,
+    SettingsItem::Deadzone,
+    SettingsItem::Smoothing,
+    SettingsItem::TurnRate,
+    SettingsItem::Snapping,
+    SettingsItem::BotLevel,
+    SettingsItem::Controls,
+    SettingsItem::Back,
+];
+
+/// Vertical position of the first item of the settings image and the space between items
+const SETTINGS_FIRST_ITEM_Y: f32 = -0.238;
+const SETTINGS_ITEM_SPACING: f32 = 0.079;
+
+/// Horizontal position of values in the settings, rules and gong images
+const MENU_VALUE_X: f32 = 0.5;
+
+/// Edit the preferences resource, preferences are saved when leaving
+///
+/// Aim items edit the aim of the player of the gamepad which opened the settings, or the default
+/// aim of preferences when no player opened them.
+pub struct Settings {
+    /// Gamepad of the player whose aim is edited
+    gamepad_id: Option<usize>,
+    selected: usize,
+}
+
+impl Settings {
+    pub fn new(gamepad_id: Option<usize>, world: &specs::World) -> Self {
+        let slots = world.read_resource::<::resource::Slots>();
+        Settings {
+            gamepad_id: gamepad_id.filter(|id| slots.gamepad_slot(*id).is_some()),
+            selected: 0,
+        }
+    }
+
+    fn aim(&self, world: &specs::World) -> ::binding::AimConfig {
+        match self.gamepad_id {
+            Some(id) => player_aim(id, world),
+            None => world.read_resource::<::preferences::Preferences>().aim.clone(),
+        }
+    }
+
+    /// Give the aim to the player, their controller model remembers it for its next players
+    fn set_aim(&self, aim: ::binding::AimConfig, world: &specs::World) {
+        let mut preferences = world.write_resource::<::preferences::Preferences>();
+        let id = match self.gamepad_id {
+            Some(id) => id,
+            None => {
+                preferences.aim = aim;
+                return;
+            }
+        };
+        if let Some(uuid) = world.read_resource::<::resource::GamepadUuids>().get(&id) {
+            preferences.profiles
+                .entry(::binding::uuid_key(uuid))
+                .or_insert_with(::binding::Profile::default)
+                .aim_config = Some(aim.clone());
+        }
+        if let Some(slot) = world.write_resource::<::resource::Slots>().gamepad_slot_mut(id) {
+            slot.aim = Some(aim);
+        }
+    }
+}
+
+impl Menu for Settings {
+    fn items(&self) -> usize {
+        SETTINGS_ITEMS.len()
+    }
+    fn selected_mut(&mut self) -> &mut usize {
+        &mut self.selected
+    }
+    /// Change the selected preference, `gamepad_id` is the gamepad to rebind with the controls item
+    fn confirm(&mut self, forward: bool, gamepad_id: Option<usize>, world: &mut specs::World) -> Trans {
+        let mut aim = self.aim(world);
+        match SETTINGS_ITEMS[self.selected] {
+            SettingsItem::Fullscreen => {
+                let mut preferences = world.write_resource::<::preferences::Preferences>();
+                preferences.fullscreen = !preferences.fullscreen;
+                return Trans::None;
+            }
+            SettingsItem::Deadzone => {
+                let step = if forward { ::preferences::DEADZONE_STEP } else { -::preferences::DEADZONE_STEP };
+                aim.deadzone = (aim.deadzone + step).max(0.0).min(::preferences::MAX_DEADZONE);
+            }
+            SettingsItem::Smoothing => {
+                let step = if forward { ::preferences::SMOOTHING_STEP } else { -::preferences::SMOOTHING_STEP };
+                aim.smoothing = (aim.smoothing + step).max(0.0).min(::preferences::MAX_SMOOTHING);
+            }
+            SettingsItem::TurnRate => {
+                let count = ::preferences::TURN_RATES.len();
+                let index = ::preferences::TURN_RATES.iter().position(|rate| *rate == aim.turn_rate).unwrap_or(0);
+                let index = if forward { (index + 1) % count } else { (index + count - 1) % count };
+                aim.turn_rate = ::preferences::TURN_RATES[index];
+            }
+            SettingsItem::Snapping => {
+                let count = ::preferences::SNAPS.len();
+                let index = ::preferences::SNAPS.iter().position(|snap| *snap == aim.snap).unwrap_or(0);
+                let index = if forward { (index + 1) % count } else { (index + count - 1) % count };
+                aim.snap = ::preferences::SNAPS[index];
+            }
+            SettingsItem::BotLevel => {
+                let mut preferences = world.write_resource::<::preferences::Preferences>();
+                preferences.bot_difficulty = preferences.bot_difficulty.step(forward);
+                return Trans::None;
+            }
+            SettingsItem::Controls => {
+                let uuid = gamepad_id.and_then(|id| {
+                    world.read_resource::<::resource::GamepadUuids>().get(&id).map(|uuid| (id, *uuid))
+                });
+                return match (gamepad_id, uuid) {
+                    (_, Some((id, uuid))) => Trans::Push(Box::new(Rebind::gamepad(id, uuid, world))),
+                    (None, _) => Trans::Push(Box::new(Rebind::keyboard(world))),
+                    _ => Trans::None,
+                };
+            }
+            SettingsItem::Back => return Trans::Pop,
+        }
+        self.set_aim(aim, world);
+        Trans::None
+    }
+}
+
+impl GameState for Settings {
+    fn on_exit(&mut self, world: &mut specs::World) {
+        if let Err(error) = world.read_resource::<::preferences::Preferences>().save() {
+            ::error::log(error);
+        }
+    }
+    fn update(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Settings);
+        show_cursor(world, SETTINGS_FIRST_ITEM_Y, SETTINGS_ITEM_SPACING, self.selected);
+
+        let aim = self.aim(world);
+        let preferences = world.read_resource::<::preferences::Preferences>();
+        let slots = world.read_resource::<::resource::Slots>();
+        let mut sprites = world.write_resource::<::resource::Sprites>();
+        let y = |item: usize| SETTINGS_FIRST_ITEM_Y + item as f32 * SETTINGS_ITEM_SPACING;
+        // A ball of the slot color beside the title tells whose aim it is
+        if let Some(slot) = self.gamepad_id.and_then(|id| slots.gamepad_slot(id)) {
+            sprites.push(::resource::Sprite {
+                image: ::Image::Ball,
+                position: [-0.42, -0.38],
+                height: 0.1,
+                color: ::resource::PLAYER_COLORS[slot.color],
+            });
+        }
+        sprites.push(::resource::Sprite {
+            image: if preferences.fullscreen { ::Image::On } else { ::Image::Off },
+            position: [MENU_VALUE_X, y(0)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        // Deadzone and smoothing are shown as gauges of small balls
+        let gauges = [
+            (aim.deadzone / ::preferences::DEADZONE_STEP, 1),
+            (aim.smoothing / ::preferences::SMOOTHING_STEP, 2),
+        ];
+        for &(count, item) in &gauges {
+            for i in 0..count.round() as usize {
+                sprites.push(::resource::Sprite {
+                    image: ::Image::Ball,
+                    position: [MENU_VALUE_X - 0.1 + i as f32 * 0.035, y(item)],
+                    height: 0.03,
+                    color: [1.0; 4],
+                });
+            }
+        }
+        // Turn rate is shown in degree per second
+        match aim.turn_rate {
+            Some(rate) => sprites.push_number(rate.to_degrees().round() as usize, [MENU_VALUE_X, y(3)], 0.04),
+            None => sprites.push(::resource::Sprite {
+                image: ::Image::Off,
+                position: [MENU_VALUE_X, y(3)],
+                height: 0.04,
+                color: [1.0; 4],
+            }),
+        }
+        sprites.push(::resource::Sprite {
+            image: match aim.snap {
+                8 => ::Image::Snap8,
+                16 => ::Image::Snap16,
+                _ => ::Image::Off,
+            },
+            position: [MENU_VALUE_X, y(4)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+        sprites.push(::resource::Sprite {
+            image: bot_image(preferences.bot_difficulty),
+            position: [MENU_VALUE_X, y(5)],
+            height: 0.04,
+            color: [1.0; 4],
+        });
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        self.menu_event(event, world)
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        self.menu_key(key, world)
+    }
+    fn paused(&self, _world: &specs::World) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy)]
+enum BindStep {
+    Aim(usize),
+    Jump(usize),
+    /// Index in `binding::ACTIONS`
+    Action(usize),
+    /// Index in `binding::MENU_KEYS`
+    MenuKey(usize),
+}
+
+const BIND_STEPS: [BindStep; 10] = [
+    BindStep::Aim(0),
+    BindStep::Jump(0),
+    BindStep::Aim(1),
+    BindStep::Jump(1),
+    BindStep::Action(0),
+    BindStep::Action(1),
+    BindStep::Action(2),
+    BindStep::Action(3),
+    BindStep::Action(4),
+    BindStep::Action(5),
+];
+
+const KEY_BIND_STEPS: [BindStep; 6] = [
+    BindStep::MenuKey(0),
+    BindStep::MenuKey(1),
+    BindStep::MenuKey(2),
+    BindStep::MenuKey(3),
+    BindStep::MenuKey(4),
+    BindStep::MenuKey(5),
+];
+
+/// Axis value from which moving a stick binds it
+const BIND_AXIS_THRESHOLD: f32 = 0.7;
+
+/// Bindings being edited
+enum BindTarget {
+    Gamepad {
+        id: usize,
+        uuid: [u8; 16],
+        profile: ::binding::Profile,
+    },
+    Keyboard(::binding::KeyProfile),
+}
+
+/// Ask for each binding of a gamepad or of the keyboard in turn, they are stored in preferences at
+/// the end. `Escape` cancels.
+pub struct Rebind {
+    target: BindTarget,
+    step: usize,
+}
+
+impl Rebind {
+    pub fn gamepad(gamepad_id: usize, uuid: [u8; 16], world: &specs::World) -> Self {
+        Rebind {
+            target: BindTarget::Gamepad {
+                id: gamepad_id,
+                uuid,
+                profile: world.read_resource::<::preferences::Preferences>().profile(&uuid),
+            },
+            step: 0,
+        }
+    }
+
+    pub fn keyboard(world: &specs::World) -> Self {
+        Rebind {
+            target: BindTarget::Keyboard(world.read_resource::<::preferences::Preferences>().keys.clone()),
+            step: 0,
+        }
+    }
+
+    fn steps(&self) -> &'static [BindStep] {
+        match self.target {
+            BindTarget::Gamepad { .. } => &BIND_STEPS,
+            BindTarget::Keyboard(_) => &KEY_BIND_STEPS,
+        }
+    }
+
+    fn next(&mut self, world: &mut specs::World) -> Trans {
+        self.step += 1;
+        if self.step < self.steps().len() {
+            return Trans::None;
+        }
+        let mut preferences = world.write_resource::<::preferences::Preferences>();
+        match self.target {
+            BindTarget::Gamepad { ref uuid, ref profile, .. } => {
+                preferences.profiles.insert(::binding::uuid_key(uuid), profile.clone());
+            }
+            BindTarget::Keyboard(ref keys) => preferences.keys = keys.clone(),
+        }
+        Trans::Pop
+    }
+}
+
+impl GameState for Rebind {
+    fn update(&mut self, _world: &mut specs::World) -> Trans {
+        Trans::None
+    }
+    fn render(&self, world: &specs::World) {
+        world.write_resource::<::resource::DrawImage>().0 = Some(::Image::Rebind);
+        let image = match self.steps()[self.step] {
+            BindStep::Aim(0) => ::Image::BindAim1,
+            BindStep::Jump(0) => ::Image::BindJump1,
+            BindStep::Aim(_) => ::Image::BindAim2,
+            BindStep::Jump(_) => ::Image::BindJump2,
+            BindStep::Action(i) => match ::binding::ACTIONS[i] {
+                ::binding::Action::Confirm => ::Image::BindConfirm,
+                ::binding::Action::Back => ::Image::BindBack,
+                ::binding::Action::Option => ::Image::BindOption,
+                ::binding::Action::AddBot => ::Image::BindAddBot,
+                ::binding::Action::RemoveBot => ::Image::BindRemoveBot,
+                ::binding::Action::Start => ::Image::BindStart,
+            },
+            BindStep::MenuKey(i) => match ::binding::MENU_KEYS[i] {
+                ::binding::MenuKey::Up => ::Image::BindUp,
+                ::binding::MenuKey::Down => ::Image::BindDown,
+                ::binding::MenuKey::Left => ::Image::BindLeft,
+                ::binding::MenuKey::Right => ::Image::BindRight,
+                ::binding::MenuKey::Confirm => ::Image::BindConfirm,
+                ::binding::MenuKey::Back => ::Image::BindBack,
+            },
+        };
+        world.write_resource::<::resource::Sprites>().push(::resource::Sprite {
+            image,
+            position: [0.0, 0.086],
+            height: 0.06,
+            color: [1.0; 4],
+        });
+    }
+    fn event(&mut self, event: gilrs::ev::Event, world: &mut specs::World) -> Trans {
+        let step = self.steps()[self.step];
+        {
+            let profile = match self.target {
+                BindTarget::Gamepad { id, ref mut profile, .. } if id == event.id => profile,
+                _ => return Trans::None,
+            };
+            match (step, event.event) {
+                (BindStep::Aim(part), gilrs::ev::EventType::AxisChanged(axis, value, _)) => {
+                    match ::binding::Axis::from_gilrs(axis) {
+                        Some(axis) if value.abs() > BIND_AXIS_THRESHOLD => profile.bind_aim(part, axis.stick()),
+                        _ => return Trans::None,
+                    }
+                }
+                (BindStep::Jump(part), gilrs::ev::EventType::ButtonPressed(button, _)) => {
+                    match ::binding::Button::from_gilrs(button) {
+                        Some(button) => profile.bind_jump(part, button),
+                        None => return Trans::None,
+                    }
+                }
+                (BindStep::Action(i), gilrs::ev::EventType::ButtonPressed(button, _)) => {
+                    match ::binding::Button::from_gilrs(button) {
+                        Some(button) => profile.bind_action(i, button),
+                        None => return Trans::None,
+                    }
+                }
+                _ => return Trans::None,
+            }
+        }
+        self.next(world)
+    }
+    fn key(&mut self, key: ::winit::VirtualKeyCode, world: &mut specs::World) -> Trans {
+        if key == ::winit::VirtualKeyCode::Escape {
+            return Trans::Pop;
+        }
+        let step = self.steps()[self.step];
+        match (step, &mut self.target, ::binding::Key::from_winit(key)) {
+            (BindStep::MenuKey(i), &mut BindTarget::Keyboard(ref mut keys), Some(key)) => keys.bind_menu(i, key),
+            _ => return Trans::None,
+        }
+        self.next(world)
+    }
+    fn paused(&self, _world: &specs::World) -> bool {
+        true
     }
 }
@@ -6,59 +6,120 @@ pub struct PhysicSystem;
 
 impl<'a> specs::System<'a> for PhysicSystem {
     type SystemData = (
+        specs::Entities<'a>,
+        specs::ReadStorage<'a, ::component::Pickup>,
         specs::WriteStorage<'a, ::component::Contactor>,
+        specs::WriteStorage<'a, ::component::Impacts>,
+        specs::WriteStorage<'a, ::component::PowerUps>,
+        specs::ReadStorage<'a, ::component::Handicap>,
+        specs::WriteStorage<'a, ::component::Airjump>,
+        specs::WriteStorage<'a, ::component::Image>,
+        specs::WriteStorage<'a, ::component::RigidBody>,
         specs::ReadExpect<'a, ::resource::UpdateTime>,
-        specs::ReadExpect<'a, ::resource::BodiesMap>,
+        specs::WriteExpect<'a, ::resource::BodiesMap>,
         specs::WriteExpect<'a, ::resource::PhysicWorld>,
     );
 
     fn run(
         &mut self,
         (
+            entities,
+            pickups,
             mut contactors,
+            mut impacts,
+            mut power_ups,
+            handicaps,
+            mut airjumps,
+            mut images,
+            mut bodies,
             update_time,
-            bodies_map,
+            mut bodies_map,
             mut physic_world,
         ): Self::SystemData,
     ) {
-        physic_world.set_timestep(update_time.0);
-        physic_world.step();
-        for contact in physic_world.contact_events() {
-            let collision_world = physic_world.collision_world();
-            match contact {
-                &ncollide2d::events::ContactEvent::Started(coh1, coh2) => {
-                    let bh1 = collision_world
-                        .collision_object(coh1)
-                        .unwrap()
-                        .data()
-                        .body();
-                    let bh2 = collision_world
-                        .collision_object(coh2)
-                        .unwrap()
-                        .data()
-                        .body();
-                    let e1 = *bodies_map.get(&bh1).unwrap();
-                    let e2 = *bodies_map.get(&bh2).unwrap();
+        // Velocities before the step give the speed of the impacts
+        let velocities = (&*entities, &bodies).join()
+            .filter_map(|(entity, body)| body.get(&physic_world).ok().map(|body| (entity, body.velocity().linear)))
+            .collect::<::fnv::FnvHashMap<_, _>>();
+        for impacts in (&mut impacts).join() {
+            impacts.clear();
+        }
+
+        // Fast balls could go through the ground or other balls in one step, the step is split so
+        // that no body moves more than `SUBSTEP_DISTANCE` at once
+        let max_speed = velocities.values().map(|velocity| velocity.norm()).fold(0.0, f32::max);
+        let substeps = ((max_speed * update_time.0 / ::entity::SUBSTEP_DISTANCE).ceil() as usize)
+            .max(1)
+            .min(::entity::MAX_SUBSTEPS);
+
+        physic_world.set_timestep(update_time.0 / substeps as f32);
+        for _ in 0..substeps {
+            physic_world.step();
+            for contact in physic_world.contact_events() {
+                let collision_world = physic_world.collision_world();
+                match contact {
+                    &ncollide2d::events::ContactEvent::Started(coh1, coh2) => {
+                        let (e1, e2) = match contact_entities(coh1, coh2, collision_world, &bodies_map) {
+                            Ok(entities) => entities,
+                            Err(error) => {
+                                ::error::log(error);
+                                continue;
+                            }
+                        };
+                        if let Some(contactor) = contactors.get_mut(e1) {
+                            contactor.push(e2);
+                        }
+                        if let Some(contactor) = contactors.get_mut(e2) {
+                            contactor.push(e1);
+                        }
+                        // The ground has no body, it doesn't move
+                        let velocity = |entity| velocities.get(&entity).cloned().unwrap_or_else(::na::zero);
+                        if let Some(impacts) = impacts.get_mut(e1) {
+                            impacts.push((e2, velocity(e2) - velocity(e1)));
+                        }
+                        if let Some(impacts) = impacts.get_mut(e2) {
+                            impacts.push((e1, velocity(e1) - velocity(e2)));
+                        }
+                    }
+                    &ncollide2d::events::ContactEvent::Stopped(coh1, coh2) => {
+                        let (e1, e2) = match contact_entities(coh1, coh2, collision_world, &bodies_map) {
+                            Ok(entities) => entities,
+                            Err(error) => {
+                                ::error::log(error);
+                                continue;
+                            }
+                        };
+                        if let Some(contactor) = contactors.get_mut(e1) {
+                            contactor.retain(|&e| e != e2);
+                        }
+                        if let Some(contactor) = contactors.get_mut(e2) {
+                            contactor.retain(|&e| e != e1);
+                        }
+                    }
+                }
+            }
+            // Sensors are tracked as contacts while they intersect
+            for proximity in physic_world.proximity_events() {
+                let intersecting = proximity.new_status == ncollide2d::query::Proximity::Intersecting;
+                if intersecting == (proximity.prev_status == ncollide2d::query::Proximity::Intersecting) {
+                    continue;
+                }
+                let collision_world = physic_world.collision_world();
+                let (e1, e2) = match contact_entities(proximity.collider1, proximity.collider2, collision_world, &bodies_map) {
+                    Ok(entities) => entities,
+                    Err(error) => {
+                        ::error::log(error);
+                        continue;
+                    }
+                };
+                if intersecting {
                     if let Some(contactor) = contactors.get_mut(e1) {
                         contactor.push(e2);
                     }
                     if let Some(contactor) = contactors.get_mut(e2) {
                         contactor.push(e1);
                     }
-                }
-                &ncollide2d::events::ContactEvent::Stopped(coh1, coh2) => {
-                    let bh1 = collision_world
-                        .collision_object(coh1)
-                        .unwrap()
-                        .data()
-                        .body();
-                    let bh2 = collision_world
-                        .collision_object(coh2)
-                        .unwrap()
-                        .data()
-                        .body();
-                    let e1 = *bodies_map.get(&bh1).unwrap();
-                    let e2 = *bodies_map.get(&bh2).unwrap();
+                } else {
                     if let Some(contactor) = contactors.get_mut(e1) {
                         contactor.retain(|&e| e != e2);
                     }
@@ -68,31 +129,982 @@ impl<'a> specs::System<'a> for PhysicSystem {
                 }
             }
         }
+
+        // Balls touching a pickup take its power-up, a pickup goes to the first ball only
+        let mut picked: Vec<(specs::Entity, specs::Entity, ::power_up::PowerUp)> = vec![];
+        for (entity, contactor, _) in (&*entities, &contactors, &power_ups).join() {
+            for contact in contactor.iter() {
+                if let Some(pickup) = pickups.get(*contact) {
+                    if !picked.iter().any(|&(_, taken, _)| taken == *contact) {
+                        picked.push((entity, *contact, pickup.power_up));
+                    }
+                }
+            }
+        }
+        for (ball, pickup, power_up) in picked {
+            entities.delete(pickup).unwrap();
+            for contactor in (&mut contactors).join() {
+                contactor.retain(|&e| e != pickup);
+            }
+
+            if power_up == ::power_up::PowerUp::Airjump {
+                if let Some(airjump) = airjumps.get_mut(ball) {
+                    airjump.charges += 1;
+                }
+                continue;
+            }
+            let shape = match (power_ups.get_mut(ball), handicaps.get(ball)) {
+                (Some(power_ups), Some(handicap)) => {
+                    ::power_up::add(power_ups, power_up);
+                    ::power_up::ball_shape(power_ups, handicap)
+                }
+                _ => continue,
+            };
+            if power_up.reshapes() {
+                let result = ::entity::set_ball_shape(
+                    ball,
+                    shape,
+                    &mut bodies,
+                    &mut images,
+                    &mut contactors,
+                    &mut physic_world,
+                    &mut bodies_map,
+                );
+                if let Err(error) = result {
+                    ::error::log(error);
+                }
+            }
+        }
     }
 }
 
+fn collision_object_entity(
+    handle: ncollide2d::world::CollisionObjectHandle,
+    collision_world: &::nphysics2d::world::CollisionWorld<f32>,
+    bodies_map: &::resource::BodiesMap,
+) -> Result<specs::Entity, ::error::PhysicError> {
+    let body_handle = collision_world
+        .collision_object(handle)
+        .ok_or(::error::PhysicError::MissingCollisionObject(handle))?
+        .data()
+        .body();
+    bodies_map
+        .get(&body_handle)
+        .cloned()
+        .ok_or(::error::PhysicError::UnmappedBody(body_handle))
+}
+
+fn contact_entities(
+    coh1: ncollide2d::world::CollisionObjectHandle,
+    coh2: ncollide2d::world::CollisionObjectHandle,
+    collision_world: &::nphysics2d::world::CollisionWorld<f32>,
+    bodies_map: &::resource::BodiesMap,
+) -> Result<(specs::Entity, specs::Entity), ::error::PhysicError> {
+    Ok((
+        collision_object_entity(coh1, collision_world, bodies_map)?,
+        collision_object_entity(coh2, collision_world, bodies_map)?,
+    ))
+}
+
 pub struct AirjumpSystem;
 
 impl<'a> specs::System<'a> for AirjumpSystem {
     type SystemData = (
         specs::ReadStorage<'a, ::component::AirjumpRestorer>,
+        specs::ReadStorage<'a, ::component::Ground>,
         specs::ReadStorage<'a, ::component::Contactor>,
+        specs::ReadStorage<'a, ::component::Handicap>,
         specs::WriteStorage<'a, ::component::Airjump>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::ReadExpect<'a, ::rules::Rules>,
     );
 
     fn run(
         &mut self,
         (
             restorers,
+            grounds,
             contactors,
+            handicaps,
             mut airjumps,
+            update_time,
+            rules,
         ): Self::SystemData,
     ) {
-        for (airjump, contactor) in (&mut airjumps, &contactors).join() {
-            for contact in contactor.iter() {
-                if restorers.get(*contact).is_some() {
-                    airjump.0 = true;
+        for (airjump, contactor, handicap) in (&mut airjumps, &contactors, &handicaps).join() {
+            let max_airjumps = rules.max_airjumps + handicap.extra_airjumps;
+            let restored = contactor.iter()
+                .filter(|contact| grounds.get(**contact).is_some() || !rules.ground_restore_only)
+                .any(|contact| restorers.get(*contact).is_some());
+            if restored {
+                airjump.charges = max_airjumps;
+            }
+
+            if airjump.charges >= max_airjumps {
+                airjump.regen = 0.0;
+            } else if let Some(regen_time) = rules.airjump_regen {
+                airjump.regen += update_time.0;
+                if airjump.regen >= regen_time {
+                    airjump.regen -= regen_time;
+                    airjump.charges += 1;
+                }
+            }
+        }
+    }
+}
+
+pub struct ModeSystem;
+
+impl<'a> specs::System<'a> for ModeSystem {
+    type SystemData = (
+        specs::Entities<'a>,
+        specs::ReadStorage<'a, ::component::Player>,
+        specs::ReadStorage<'a, ::component::Input>,
+        specs::ReadStorage<'a, ::component::Contactor>,
+        specs::ReadStorage<'a, ::component::Ground>,
+        specs::ReadStorage<'a, ::component::Gong>,
+        specs::ReadStorage<'a, ::component::Checkpoint>,
+        specs::ReadStorage<'a, ::component::Impacts>,
+        specs::ReadStorage<'a, ::component::Invulnerable>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::WriteStorage<'a, ::component::It>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::ReadExpect<'a, ::rules::Rules>,
+        specs::ReadExpect<'a, ::resource::PhysicWorld>,
+        specs::ReadExpect<'a, ::mode::RoundTimer>,
+        specs::WriteExpect<'a, ::mode::RoundState>,
+        specs::WriteExpect<'a, ::mode::RaceRecords>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            players,
+            inputs,
+            contactors,
+            grounds,
+            gongs,
+            checkpoints,
+            impacts,
+            invulnerables,
+            bodies,
+            mut its,
+            update_time,
+            rules,
+            physic_world,
+            timer,
+            mut round,
+            mut records,
+        ): Self::SystemData,
+    ) {
+        round.time += update_time.0;
+
+        match rules.mode {
+            ::rules::Mode::Gong => {
+                for (_, impacts) in (&gongs, &impacts).join() {
+                    for &(ball, velocity) in impacts.iter() {
+                        let slot = match (players.get(ball), inputs.get(ball)) {
+                            (Some(player), Some(_)) => player.0,
+                            _ => continue,
+                        };
+                        if rules.gong.min_speed.map(|min_speed| velocity.norm() >= min_speed).unwrap_or(true) {
+                            *round.gong_hits.entry(slot).or_insert(0) += 1;
+                        }
+                    }
+                }
+            }
+            ::rules::Mode::KingOfTheHill => {
+                let hill = ::na::Vector2::new(::mode::HILL_POSITION[0], ::mode::HILL_POSITION[1]);
+                let mut kings = vec![];
+                for (player, body) in (&players, &bodies).join() {
+                    let position = match body.get(&physic_world) {
+                        Ok(body) => body.position().translation.vector,
+                        Err(error) => {
+                            ::error::log(error);
+                            continue;
+                        }
+                    };
+                    if (position - hill).norm() < ::mode::HILL_RADIUS && !kings.contains(&player.0) {
+                        kings.push(player.0);
+                    }
+                }
+                for slot in kings {
+                    *round.hill.entry(slot).or_insert(0.0) += update_time.0;
+                }
+            }
+            ::rules::Mode::Tag => {
+                let it = (&*entities, &its).join().map(|(entity, _)| entity).next();
+                match it {
+                    Some(it) => {
+                        if round.time - round.tagged_at < ::mode::TAG_COOLDOWN {
+                            return;
+                        }
+                        // Pass "it" to a ball of another player it touches, unless it just respawned
+                        let slot = players.get(it).map(|player| player.0);
+                        let tagged = contactors.get(it).and_then(|contactor| {
+                            contactor.iter()
+                                .filter(|contact| invulnerables.get(**contact).is_none())
+                                .find(|contact| players.get(**contact).map(|player| Some(player.0) != slot).unwrap_or(false))
+                                .cloned()
+                        });
+                        if let Some(tagged) = tagged {
+                            its.remove(it);
+                            its.insert(tagged, ::component::It);
+                            round.tagged_at = round.time;
+                        }
+                    }
+                    None => {
+                        // The ball which was "it" left the game
+                        if let Some((entity, _, _)) = (&*entities, &players, &inputs).join().next() {
+                            its.insert(entity, ::component::It);
+                            round.tagged_at = round.time;
+                        }
+                    }
+                }
+            }
+            ::rules::Mode::Race => {
+                let time = round.time;
+                let mut recorded = vec![];
+                for (player, contactor, body) in (&players, &contactors, &bodies).join() {
+                    let run = round.runs.entry(player.0).or_insert_with(::mode::Run::default);
+                    if run.finish.is_some() {
+                        continue;
+                    }
+
+                    let sampled = run.path.len() * ::mode::GHOST_STEPS <= timer.steps;
+                    if !recorded.contains(&player.0) && sampled && run.path.len() < ::mode::GHOST_POINTS {
+                        recorded.push(player.0);
+                        match body.get(&physic_world) {
+                            Ok(body) => {
+                                let position = body.position().translation.vector;
+                                run.path.push([position[0], position[1]]);
+                            }
+                            Err(error) => ::error::log(error),
+                        }
+                    }
+
+                    for contact in contactor.iter() {
+                        if let Some(checkpoint) = checkpoints.get(*contact) {
+                            if checkpoint.0 == run.splits.len() {
+                                run.splits.push(time);
+                            }
+                        }
+                    }
+
+                    let finished = run.splits.len() == ::entity::CHECKPOINTS.len()
+                        && contactor.iter().any(|contact| gongs.get(*contact).is_some());
+                    if finished {
+                        run.finish = Some(time);
+                        let best = records.get(&player.0)
+                            .and_then(|record| record.finish)
+                            .map(|best| time < best)
+                            .unwrap_or(true);
+                        if best {
+                            records.insert(player.0, run.clone());
+                        }
+                    }
+                }
+            }
+            ::rules::Mode::Survival => {
+                if round.time < ::mode::SURVIVAL_GRACE {
+                    return;
+                }
+                for (entity, _, contactor, _) in (&*entities, &inputs, &contactors, !&invulnerables).join() {
+                    if contactor.iter().any(|contact| grounds.get(*contact).is_some()) {
+                        entities.delete(entity).unwrap();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Stun balls stomped by another ball and throw balls apart after fast impacts with the combat rule
+pub struct CombatSystem;
+
+impl<'a> specs::System<'a> for CombatSystem {
+    type SystemData = (
+        specs::Entities<'a>,
+        specs::ReadStorage<'a, ::component::Input>,
+        specs::ReadStorage<'a, ::component::Impacts>,
+        specs::ReadStorage<'a, ::component::Invulnerable>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::WriteStorage<'a, ::component::Stun>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::ReadExpect<'a, ::rules::Rules>,
+        specs::WriteExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            inputs,
+            impacts,
+            invulnerables,
+            bodies,
+            mut stuns,
+            update_time,
+            rules,
+            mut physic_world,
+        ): Self::SystemData,
+    ) {
+        let mut recovered = vec![];
+        for (entity, stun) in (&*entities, &mut stuns).join() {
+            stun.0 -= update_time.0;
+            if stun.0 <= 0.0 {
+                recovered.push(entity);
+            }
+        }
+        for entity in recovered {
+            stuns.remove(entity);
+        }
+
+        if !rules.combat {
+            return;
+        }
+
+        // Only impacts between balls count
+        let positions = (&*entities, &inputs, &bodies).join()
+            .filter_map(|(entity, _, body)| {
+                body.get(&physic_world).ok().map(|body| (entity, body.position().translation.vector))
+            })
+            .collect::<::fnv::FnvHashMap<_, _>>();
+        let mut stomped = vec![];
+        let mut knockbacks = vec![];
+        for (entity, impacts) in (&*entities, &impacts).join() {
+            for &(other, velocity) in impacts.iter() {
+                let delta = match (positions.get(&entity), positions.get(&other)) {
+                    (Some(position), Some(other_position)) => position - other_position,
+                    _ => continue,
+                };
+                if ::combat::stomp(delta, velocity) && invulnerables.get(entity).is_none() {
+                    stomped.push(entity);
+                }
+                if let Some(knockback) = ::combat::knockback(delta, velocity) {
+                    knockbacks.push((entity, knockback));
+                }
+            }
+        }
+
+        for entity in stomped {
+            stuns.insert(entity, ::component::Stun(::combat::STUN_TIME));
+        }
+        for (entity, knockback) in knockbacks {
+            if let Some(body) = bodies.get(entity) {
+                match body.get_mut(&mut physic_world) {
+                    Ok(body) => {
+                        let mut velocity = *body.velocity();
+                        velocity.linear += knockback;
+                        body.set_velocity(velocity);
+                    }
+                    Err(error) => ::error::log(error),
+                }
+            }
+        }
+    }
+}
+
+/// Count the steps of the round and flag the step the time runs out
+pub struct TimerSystem;
+
+impl<'a> specs::System<'a> for TimerSystem {
+    type SystemData = specs::WriteExpect<'a, ::mode::RoundTimer>;
+
+    fn run(&mut self, mut timer: Self::SystemData) {
+        timer.steps += 1;
+        if timer.length == Some(timer.steps) {
+            timer.time_up = true;
+        }
+    }
+}
+
+pub struct PowerUpSystem;
+
+impl<'a> specs::System<'a> for PowerUpSystem {
+    type SystemData = (
+        specs::Entities<'a>,
+        specs::WriteStorage<'a, ::component::Pickup>,
+        specs::WriteStorage<'a, ::component::PowerUps>,
+        specs::ReadStorage<'a, ::component::Handicap>,
+        specs::WriteStorage<'a, ::component::Contactor>,
+        specs::WriteStorage<'a, ::component::Image>,
+        specs::WriteStorage<'a, ::component::RigidBody>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::ReadExpect<'a, ::rules::Rules>,
+        specs::WriteExpect<'a, ::mode::RoundState>,
+        specs::WriteExpect<'a, ::resource::BodiesMap>,
+        specs::WriteExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            mut pickups,
+            mut power_ups,
+            handicaps,
+            mut contactors,
+            mut images,
+            mut bodies,
+            update_time,
+            rules,
+            mut round,
+            mut bodies_map,
+            mut physic_world,
+        ): Self::SystemData,
+    ) {
+        // Effects wear off, the body of the ball goes back to its shape without them
+        let mut reshaped = vec![];
+        for (entity, power_ups, handicap) in (&*entities, &mut power_ups, &handicaps).join() {
+            let mut reshape = false;
+            for effect in power_ups.iter_mut() {
+                effect.1 -= update_time.0;
+                reshape |= effect.1 <= 0.0 && effect.0.reshapes();
+            }
+            power_ups.retain(|&(_, remaining)| remaining > 0.0);
+            if reshape {
+                reshaped.push((entity, ::power_up::ball_shape(power_ups, handicap)));
+            }
+        }
+        for (entity, shape) in reshaped {
+            let result = ::entity::set_ball_shape(
+                entity,
+                shape,
+                &mut bodies,
+                &mut images,
+                &mut contactors,
+                &mut physic_world,
+                &mut bodies_map,
+            );
+            if let Err(error) = result {
+                ::error::log(error);
+            }
+        }
+
+        // Flipped balls get twice the gravity upward
+        for (power_ups, body) in (&power_ups, &bodies).join() {
+            if !power_ups.iter().any(|&(power_up, _)| power_up == ::power_up::PowerUp::GravityFlip) {
+                continue;
+            }
+            match body.get_mut(&mut physic_world) {
+                Ok(body) => {
+                    let mut velocity = *body.velocity();
+                    velocity.linear[1] -= 2.0 * ::entity::GRAVITY * update_time.0;
+                    body.set_velocity(velocity);
+                }
+                Err(error) => ::error::log(error),
+            }
+        }
+
+        if !rules.power_ups {
+            return;
+        }
+        let next_spawn = (round.pickups + 1) as f32 * ::power_up::SPAWN_INTERVAL;
+        if round.time < next_spawn {
+            return;
+        }
+        let power_up = ::power_up::PowerUp::nth(round.pickups);
+        let spot = (round.pickups * 2) % ::entity::PICKUP_SPOTS.len();
+        round.pickups += 1;
+
+        // A spot holds one pickup at a time
+        let count = pickups.join().count();
+        if count >= ::power_up::MAX_PICKUPS || pickups.join().any(|pickup| pickup.spot == spot) {
+            return;
+        }
+        ::entity::create_pickup(
+            power_up,
+            spot,
+            &entities,
+            &mut pickups,
+            &mut images,
+            &mut bodies,
+            &mut physic_world,
+            &mut bodies_map,
+        );
+    }
+}
+
+pub struct PlatformSystem;
+
+impl<'a> specs::System<'a> for PlatformSystem {
+    type SystemData = (
+        specs::WriteStorage<'a, ::component::Path>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::WriteExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            mut paths,
+            bodies,
+            update_time,
+            mut physic_world,
+        ): Self::SystemData,
+    ) {
+        for (path, body) in (&mut paths, &bodies).join() {
+            let body = match body.get_mut(&mut physic_world) {
+                Ok(body) => body,
+                Err(error) => {
+                    ::error::log(error);
+                    continue;
+                }
+            };
+            let target = ::na::Vector2::new(path.points[path.next][0], path.points[path.next][1]);
+            let delta = target - body.position().translation.vector;
+            let velocity = if delta.norm() > path.speed * update_time.0 {
+                delta * (path.speed / delta.norm())
+            } else {
+                // Reach the point during this step then go to the next one
+                path.next = (path.next + 1) % path.points.len();
+                if update_time.0 > 0.0 { delta / update_time.0 } else { ::na::Vector2::new(0.0, 0.0) }
+            };
+            body.set_velocity(::nphysics2d::math::Velocity::linear(velocity[0], velocity[1]));
+        }
+    }
+}
+
+pub struct WindSystem;
+
+impl<'a> specs::System<'a> for WindSystem {
+    type SystemData = (
+        specs::ReadStorage<'a, ::component::Wind>,
+        specs::ReadStorage<'a, ::component::Contactor>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::WriteExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            winds,
+            contactors,
+            bodies,
+            update_time,
+            mut physic_world,
+        ): Self::SystemData,
+    ) {
+        for (contactor, body) in (&contactors, &bodies).join() {
+            if !contactor.iter().any(|contact| winds.get(*contact).is_some()) {
+                continue;
+            }
+            let mut acceleration = ::na::Vector2::new(0.0, 0.0);
+            for wind in contactor.iter().filter_map(|contact| winds.get(*contact)) {
+                acceleration += ::na::Vector2::new(wind.0[0], wind.0[1]);
+            }
+            match body.get_mut(&mut physic_world) {
+                Ok(body) => {
+                    let mut velocity = *body.velocity();
+                    velocity.linear += acceleration * update_time.0;
+                    body.set_velocity(velocity);
+                }
+                Err(error) => ::error::log(error),
+            }
+        }
+    }
+}
+
+pub struct SpikeSystem;
+
+impl<'a> specs::System<'a> for SpikeSystem {
+    type SystemData = (
+        specs::Entities<'a>,
+        specs::ReadStorage<'a, ::component::Spikes>,
+        specs::ReadStorage<'a, ::component::Input>,
+        specs::ReadStorage<'a, ::component::Contactor>,
+        specs::ReadStorage<'a, ::component::Invulnerable>,
+        specs::WriteStorage<'a, ::component::Respawn>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            spikes,
+            inputs,
+            contactors,
+            invulnerables,
+            mut respawns,
+        ): Self::SystemData,
+    ) {
+        for (entity, _, contactor, _) in (&*entities, &inputs, &contactors, !&invulnerables).join() {
+            if contactor.iter().any(|contact| spikes.get(*contact).is_some()) {
+                respawns.insert(entity, ::component::Respawn);
+            }
+        }
+    }
+}
+
+pub struct RespawnSystem;
+
+impl<'a> specs::System<'a> for RespawnSystem {
+    type SystemData = (
+        specs::Entities<'a>,
+        specs::ReadStorage<'a, ::component::Input>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::WriteStorage<'a, ::component::Respawn>,
+        specs::WriteStorage<'a, ::component::Invulnerable>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::ReadExpect<'a, ::rules::Rules>,
+        specs::ReadExpect<'a, ::mode::RoundState>,
+        specs::WriteExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            inputs,
+            bodies,
+            mut respawns,
+            mut invulnerables,
+            update_time,
+            rules,
+            round,
+            mut physic_world,
+        ): Self::SystemData,
+    ) {
+        let mut vulnerables = vec![];
+        for (entity, invulnerable) in (&*entities, &mut invulnerables).join() {
+            invulnerable.0 -= update_time.0;
+            if invulnerable.0 <= 0.0 {
+                vulnerables.push(entity);
+            }
+        }
+        for entity in vulnerables {
+            invulnerables.remove(entity);
+        }
+
+        let positions = (&*entities, &inputs, &bodies).join()
+            .filter_map(|(entity, _, body)| {
+                body.get(&physic_world).ok().map(|body| (entity, body.position().translation.vector))
+            })
+            .collect::<Vec<_>>();
+
+        // Balls out of the arena respawn
+        for &(entity, position) in &positions {
+            let inside = position[0] >= ::entity::ARENA_MIN[0] && position[0] <= ::entity::ARENA_MAX[0]
+                && position[1] >= ::entity::ARENA_MIN[1] && position[1] <= ::entity::ARENA_MAX[1];
+            if !inside {
+                respawns.insert(entity, ::component::Respawn);
+            }
+        }
+
+        // In survival, once the ground is deadly, spikes and falls are deadly too
+        let eliminating = rules.mode == ::rules::Mode::Survival && round.time >= ::mode::SURVIVAL_GRACE;
+
+        let respawning = (&*entities, &respawns).join().map(|(entity, _)| entity).collect::<Vec<_>>();
+        for entity in respawning {
+            respawns.remove(entity);
+            if eliminating && invulnerables.get(entity).is_none() {
+                entities.delete(entity).unwrap();
+                continue;
+            }
+            let body = match bodies.get(entity) {
+                Some(body) => body,
+                None => continue,
+            };
+
+            // The spawn point farthest from the other balls
+            let distance = |point: &::na::Vector2<f32>| positions.iter()
+                .filter(|&&(other, _)| other != entity)
+                .map(|&(_, position)| (position - point).norm())
+                .fold(::std::f32::INFINITY, f32::min);
+            let spawn = ::entity::SPAWN_POINTS.iter()
+                .map(|point| ::na::Vector2::new(point[0], point[1]))
+                .max_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap_or(::std::cmp::Ordering::Equal));
+            let spawn = match spawn {
+                Some(spawn) => spawn,
+                None => continue,
+            };
+
+            match body.get_mut(&mut physic_world) {
+                Ok(body) => {
+                    let angle = body.position().rotation.angle();
+                    body.set_position(::na::Isometry2::new(spawn, angle));
+                    body.set_velocity(::nphysics2d::math::Velocity::zero());
+                }
+                Err(error) => ::error::log(error),
+            }
+            invulnerables.insert(entity, ::component::Invulnerable(::entity::INVULNERABILITY_TIME));
+        }
+    }
+}
+
+pub struct OneWaySystem;
+
+impl<'a> specs::System<'a> for OneWaySystem {
+    type SystemData = (
+        specs::ReadStorage<'a, ::component::OneWay>,
+        specs::ReadStorage<'a, ::component::Input>,
+        specs::ReadStorage<'a, ::component::Image>,
+        specs::ReadStorage<'a, ::component::Contactor>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::ReadStorage<'a, ::component::Handicap>,
+        specs::WriteStorage<'a, ::component::Airjump>,
+        specs::ReadExpect<'a, ::rules::Rules>,
+        specs::WriteExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            one_ways,
+            inputs,
+            images,
+            contactors,
+            bodies,
+            handicaps,
+            mut airjumps,
+            rules,
+            mut physic_world,
+        ): Self::SystemData,
+    ) {
+        // One-way platforms are sensors, balls falling on them from above are put on top and get
+        // their airjumps back like on other platforms, balls going up through them don't
+        for (_, image, contactor, body, handicap, airjump) in (&inputs, &images, &contactors, &bodies, &handicaps, &mut airjumps).join() {
+            let tops = contactor.iter()
+                .filter(|contact| one_ways.get(**contact).is_some())
+                .filter_map(|contact| bodies.get(*contact))
+                .filter_map(|platform| platform.get(&physic_world).ok())
+                .map(|platform| platform.position().translation.vector[1])
+                .collect::<Vec<_>>();
+
+            let body = match body.get_mut(&mut physic_world) {
+                Ok(body) => body,
+                Err(error) => {
+                    ::error::log(error);
+                    continue;
+                }
+            };
+            let mut position = *body.position();
+            let mut velocity = *body.velocity();
+            // The image radius of a ball is its radius, the ball lands its bottom on the middle
+            // of the platform so that they keep intersecting
+            let mut landing: Option<f32> = None;
+            for top in tops {
+                if position.translation.vector[1] < top && velocity.linear[1] > 0.0 {
+                    landing = Some(landing.map_or(top, |landing| landing.min(top)));
+                }
+            }
+            if let Some(top) = landing {
+                position.translation.vector[1] = top - image.0;
+                velocity.linear[1] = 0.0;
+                body.set_position(position);
+                body.set_velocity(velocity);
+                if !rules.ground_restore_only {
+                    airjump.charges = rules.max_airjumps + handicap.extra_airjumps;
+                }
+            }
+        }
+    }
+}
+
+pub struct BotSystem;
+
+impl<'a> specs::System<'a> for BotSystem {
+    type SystemData = (
+        specs::Entities<'a>,
+        specs::ReadStorage<'a, ::component::Gong>,
+        specs::ReadStorage<'a, ::component::Airjump>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::ReadStorage<'a, ::component::Player>,
+        specs::WriteStorage<'a, ::component::Bot>,
+        specs::WriteStorage<'a, ::component::Input>,
+        specs::ReadExpect<'a, ::rules::Rules>,
+        specs::ReadExpect<'a, ::mode::RoundState>,
+        specs::ReadExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            gongs,
+            airjumps,
+            bodies,
+            players,
+            mut bots,
+            mut inputs,
+            rules,
+            round,
+            physic_world,
+        ): Self::SystemData,
+    ) {
+        let targets = (&gongs, &bodies).join()
+            .filter_map(|(_, body)| body.get(&physic_world).ok())
+            .map(|body| body.position().translation.vector)
+            .collect::<Vec<_>>();
+
+        for (entity, bot, airjump, body, input) in (&*entities, &mut bots, &airjumps, &bodies, &mut inputs).join() {
+            // Keep the jump button held while the jump charges, a new jump needs it released first
+            let released = !input.jump;
+            input.jump = bot.hold > 0;
+            bot.hold = bot.hold.saturating_sub(1);
+            if bot.cooldown > 0 {
+                bot.cooldown -= 1;
+                continue;
+            }
+            bot.cooldown = bot.difficulty.reaction();
+
+            let body = match body.get(&physic_world) {
+                Ok(body) => body,
+                Err(error) => {
+                    ::error::log(error);
+                    continue;
+                }
+            };
+            // In race mode the checkpoints come before the gong
+            let checkpoint = if rules.mode == ::rules::Mode::Race {
+                let passed = players.get(entity)
+                    .and_then(|player| round.runs.get(&player.0))
+                    .map(|run| run.splits.len())
+                    .unwrap_or(0);
+                ::entity::CHECKPOINTS.get(passed).map(|position| ::na::Vector2::new(position[0], position[1]))
+            } else {
+                None
+            };
+            // Otherwise the nearest gong
+            let position = body.position().translation.vector;
+            let gong = targets.iter()
+                .min_by(|a, b| {
+                    (*a - position).norm()
+                        .partial_cmp(&(*b - position).norm())
+                        .unwrap_or(::std::cmp::Ordering::Equal)
+                })
+                .cloned();
+            let target = match checkpoint.or(gong) {
+                Some(target) => target,
+                None => continue,
+            };
+
+            // Aim above the target to compensate the fall during the flight
+            let delta = target - body.position().translation.vector;
+            let time = delta.norm() / ::entity::BALL_VELOCITY;
+            let aim = ::na::Vector2::new(delta[0], delta[1] - 0.5 * ::entity::GRAVITY * time * time);
+            let error = bot.random() * bot.difficulty.aim_error();
+            input.angle = Some(aim[1].atan2(aim[0]) + error);
+
+            // Jump once the ball stops going up to get the highest, charged jumps at full strength
+            if released && airjump.available() && body.velocity().linear[1] <= 0.0 {
+                input.jump = true;
+                if rules.jump_strength == ::rules::JumpStrength::Charged {
+                    bot.hold = (::rules::CHARGE_TIME / ::FIXED_STEP).ceil() as usize;
+                }
+            }
+        }
+    }
+}
+
+pub struct ControlSystem;
+
+impl<'a> specs::System<'a> for ControlSystem {
+    type SystemData = (
+        specs::Entities<'a>,
+        specs::ReadStorage<'a, ::component::Input>,
+        specs::ReadStorage<'a, ::component::AimFilter>,
+        specs::ReadStorage<'a, ::component::Handicap>,
+        specs::ReadStorage<'a, ::component::Stun>,
+        specs::ReadStorage<'a, ::component::RigidBody>,
+        specs::WriteStorage<'a, ::component::Airjump>,
+        specs::WriteStorage<'a, ::component::JumpCharge>,
+        specs::ReadExpect<'a, ::resource::UpdateTime>,
+        specs::ReadExpect<'a, ::rules::Rules>,
+        specs::WriteExpect<'a, ::resource::PhysicWorld>,
+    );
+
+    fn run(
+        &mut self,
+        (
+            entities,
+            inputs,
+            filters,
+            handicaps,
+            stuns,
+            bodies,
+            mut airjumps,
+            mut charges,
+            update_time,
+            rules,
+            mut physic_world,
+        ): Self::SystemData,
+    ) {
+        for (entity, input, handicap, airjump, body) in (&*entities, &inputs, &handicaps, &mut airjumps, &bodies).join() {
+            let body = match body.get_mut(&mut physic_world) {
+                Ok(body) => body,
+                Err(error) => {
+                    ::error::log(error);
+                    continue;
+                }
+            };
+
+            // Set angle
+            if let Some(next_angle) = input.angle {
+                let current_angle = body.position().rotation.angle();
+                // Turn by the shortest way
+                let mut delta = ::wrap_angle(next_angle - current_angle);
+                if let Some(filter) = filters.get(entity) {
+                    delta *= 1.0 - filter.smoothing.powf(update_time.0 * 60.0);
+                    if let Some(turn_rate) = filter.turn_rate {
+                        let max_delta = turn_rate * update_time.0;
+                        delta = delta.max(-max_delta).min(max_delta);
+                    }
+                }
+                body.apply_displacement(&::nphysics2d::math::Velocity::angular(delta));
+            }
+
+            // Stunned balls still aim but can't jump, a jump being charged is lost and a button
+            // held through the stun doesn't jump when it ends
+            if stuns.get(entity).is_some() {
+                if input.jump {
+                    charges.insert(entity, ::component::JumpCharge { jumped: true, ..Default::default() });
+                } else {
+                    charges.remove(entity);
+                }
+                continue;
+            }
+
+            // Jump once per press of the button
+            let previous = charges.get(entity).cloned();
+            let strength = if input.jump {
+                let mut charge = previous.unwrap_or_default();
+                charge.time += update_time.0;
+                charge.peak = charge.peak.max(input.pressure.unwrap_or(1.0));
+                let strength = if charge.jumped {
+                    None
+                } else {
+                    match rules.jump_strength {
+                        ::rules::JumpStrength::Fixed => Some(1.0),
+                        ::rules::JumpStrength::Analog if charge.time >= ::rules::ANALOG_WINDOW => Some(charge.peak),
+                        _ => None,
+                    }
+                };
+                charge.jumped |= strength.is_some();
+                charges.insert(entity, charge);
+                strength
+            } else {
+                charges.remove(entity);
+                match (rules.jump_strength, previous) {
+                    (::rules::JumpStrength::Fixed, _) | (_, None) => None,
+                    (_, Some(charge)) if charge.jumped => None,
+                    (::rules::JumpStrength::Analog, Some(charge)) => Some(charge.peak),
+                    (::rules::JumpStrength::Charged, Some(charge)) => Some(charge.time / ::rules::CHARGE_TIME),
+                }
+            };
+            if let (Some(strength), true) = (strength, airjump.available()) {
+                airjump.charges -= 1;
+                let angle = body.position().rotation.angle();
+                let speed = ::rules::jump_speed(strength) * handicap.jump;
+                let mut velocity = ::na::Vector2::new(angle.cos() * speed, angle.sin() * speed);
+                if rules.jump_velocity == ::rules::JumpVelocity::Add {
+                    velocity += body.velocity().linear;
                 }
+                body.set_velocity(::nphysics2d::math::Velocity::linear(velocity[0], velocity[1]));
             }
         }
     }